@@ -0,0 +1,31 @@
+#!/bin/bash
+# 用 benches 目录下的脚本比较两种执行方式、两种值表示（默认的枚举和 nan-boxing 特性）的速度。
+# 每个脚本最后一行输出的是自己用 clock() 量出的秒数，每种组合跑十次取最快的一次；
+# 三次在单核、有其它负载的机器上不够，同一个程序前后能差 20%。
+
+set -euo pipefail
+
+cargo build --release
+cp ./target/release/jlox-rust ./target/release/jlox-rust-enum
+cargo build --release --features nan-boxing
+cp ./target/release/jlox-rust ./target/release/jlox-rust-nan-boxing
+
+printf '%-20s %12s %12s %16s %16s\n' benchmark tree-walker vm 'tree-walker(nb)' 'vm(nb)'
+for script in benches/*.lox; do
+    times=()
+    for bin in ./target/release/jlox-rust-enum ./target/release/jlox-rust-nan-boxing; do
+        for backend in "" --vm; do
+            best=""
+            for _ in $(seq 10); do
+                # shellcheck disable=SC2086
+                t=$($bin $backend "$script" | tail -n 1)
+                if [ -z "$best" ] || awk "BEGIN { exit !($t < $best) }"; then
+                    best=$t
+                fi
+            done
+            times+=("$best")
+        done
+    done
+    printf '%-20s %11.3fs %11.3fs %15.3fs %15.3fs\n' "$(basename "$script")" \
+        "${times[0]}" "${times[1]}" "${times[2]}" "${times[3]}"
+done
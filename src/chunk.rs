@@ -0,0 +1,187 @@
+use std::rc::Rc;
+
+use crate::symbol::Symbol;
+
+// 定义指令枚举，同时生成按编号排列的指令表，解码时查表，不必手写每个编号
+macro_rules! opcodes {
+    ($($(#[$doc:meta])* $name:ident,)*) => {
+        /// 字节码指令。操作数紧跟在指令后面：常量、变量槽位、跳转距离等都是两个字节（小端），
+        /// 参数个数和标志是一个字节。
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        #[repr(u8)]
+        pub enum OpCode {
+            $($(#[$doc])* $name,)*
+        }
+        impl OpCode {
+            const ALL: &[OpCode] = &[$(OpCode::$name,)*];
+        }
+    };
+}
+opcodes! {
+    /// 常量下标：把常量压栈
+    Constant,
+    Nil,
+    True,
+    False,
+    /// 没有初始值的变量：兼容模式下是 nil，否则是未初始化
+    Uninitialized,
+    Pop,
+    /// 槽位
+    GetLocal,
+    SetLocal,
+    /// 名字常量
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    /// 上值下标
+    GetUpvalue,
+    SetUpvalue,
+    /// 名字常量：栈顶是未初始化的值时报错，用于可能没有初始值的局部变量
+    CheckInitialized,
+    /// 名字常量
+    GetProperty,
+    SetProperty,
+    /// 名字常量：给字段赋值前检查对象是不是实例，和树遍历解释器一样先于右边的值报错
+    CheckInstance,
+    /// 名字常量：弹出父类和 this，压入绑定好的父类方法
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    In,
+    Not,
+    Negate,
+    Print,
+    /// 向前跳转的距离
+    Jump,
+    /// 向前跳转的距离：栈顶为假时跳转，不弹出
+    JumpIfFalse,
+    /// 向后跳转的距离
+    Loop,
+    /// 参数个数（一个字节）
+    Call,
+    /// 函数常量，之后每个上值一个字节的 is_local 和两个字节的下标
+    Closure,
+    CloseUpvalue,
+    Return,
+    /// 名字常量、方法个数、是否有父类（一个字节）：弹出方法，压入类
+    Class,
+    /// 栈顶的父类必须是类
+    CheckSuperclass,
+    /// 元素个数
+    List,
+    /// 条目个数
+    Map,
+    /// 栈顶的键必须能作为映射的键
+    CheckKey,
+    GetIndex,
+    SetIndex,
+    /// 把栈顶的值转成字符串，字符串插值里每一部分求值后立刻转换
+    Stringify,
+    /// 部分个数：把这些字符串拼接起来
+    Interpolate,
+    /// 开始执行一条语句：计一步，检查执行限制
+    Step,
+    /// 和 Step 一样，同时记下语句的位置，用于调用栈
+    StepAt,
+}
+impl OpCode {
+    /// 不是合法指令的字节返回 None。
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+}
+
+/// 常量池里的值：数字、字符串和编译好的函数。
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(Symbol),
+    Function(Rc<Function>),
+}
+
+/// 行号表的一项：从 offset 开始的指令都来自源码的这个位置，直到下一项。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// 源码里的一个块语句对应的指令范围 [start, end)，以及块语句本身的位置（没有时行号为 0）。
+/// 树遍历解释器执行块时会在调用栈里压一帧，虚拟机出错时按这张表补出同样的帧。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// 一段字节码：指令、常量池和行号表。
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    pub lines: Vec<Line>,
+    pub blocks: Vec<Block>,
+}
+impl Chunk {
+    pub fn new() -> Self {
+        Chunk::default()
+    }
+    pub fn write(&mut self, byte: u8, line: usize, column: usize) {
+        // 行号表按位置变化记录，连续来自同一位置的指令只占一项
+        match self.lines.last() {
+            Some(last) if last.line == line && last.column == column => {}
+            _ => self.lines.push(Line {
+                offset: self.code.len(),
+                line,
+                column,
+            }),
+        }
+        self.code.push(byte);
+    }
+    /// 返回常量的下标。
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+    /// 偏移处的指令来自源码的哪一行哪一列。
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let i = self.lines.partition_point(|line| line.offset <= offset);
+        match i.checked_sub(1).map(|i| self.lines[i]) {
+            Some(line) => (line.line, line.column),
+            None => (0, 0),
+        }
+    }
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.code[offset], self.code[offset + 1]])
+    }
+}
+
+/// 编译好的函数：顶层脚本也编译成一个没有参数的函数。
+#[derive(Debug, Clone, Default)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    /// 类的 init 方法：无论怎样返回，结果都是 this
+    pub is_initializer: bool,
+    pub chunk: Chunk,
+}
+impl Function {
+    pub fn new(name: impl Into<String>) -> Self {
+        Function {
+            name: name.into(),
+            ..Function::default()
+        }
+    }
+}
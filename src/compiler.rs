@@ -0,0 +1,793 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    chunk::{Block, Constant, Function, OpCode},
+    error::{Diagnostic, LoxError, Span},
+    expr::{Expr, FunctionDecl, Stmt},
+    symbol::Symbol,
+    token::{Object, Token},
+    token_type::TokenType,
+};
+
+// 正在编译的函数的种类，决定槽位 0 放什么以及默认的返回值
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // 被内层函数捕获的局部变量，离开作用域时要关闭上值而不是直接弹出
+    captured: bool,
+    // 声明时没有初始值，读取时要检查是否已经赋值
+    uninitialized: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u16,
+    // true 表示捕获外层函数的局部变量，false 表示外层函数自己的上值
+    is_local: bool,
+    uninitialized: bool,
+}
+
+// 正在编译的循环：break 和 continue 要弹出循环里面声明的局部变量，再跳到循环的出口或末尾
+struct Loop {
+    label: Option<Symbol>,
+    locals: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    String(Symbol),
+}
+
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    // 相同的数字和字符串常量只存一份
+    constants: HashMap<ConstantKey, u16>,
+}
+impl FunctionState {
+    fn new(function: Function, kind: FunctionKind) -> Self {
+        // 槽位 0 留给被调用的函数本身；方法里是 this
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        FunctionState {
+            function,
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                captured: false,
+                uninitialized: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+            constants: HashMap::new(),
+        }
+    }
+}
+
+// 变量解析到的位置
+enum Variable {
+    Local(u16, bool),
+    Upvalue(u16, bool),
+    Global(u16),
+}
+
+/// 把解析并检查过的语法树编译成字节码。顶层代码编译成一个名为 `<script>` 的函数。
+///
+/// 变量的作用域在这里按词法重新计算一遍，规则和 [`crate::Resolver`] 一致；
+/// resolver 报告过的静态错误这里不再检查。
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+    // 接下来写入的指令对应的源码位置
+    line: usize,
+    column: usize,
+    errors: Vec<LoxError>,
+}
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            functions: Vec::new(),
+            line: 0,
+            column: 0,
+            errors: Vec::new(),
+        }
+    }
+    /// 最后一条语句是表达式语句时，脚本返回它的值，其余情况返回 nil。
+    pub fn compile(&mut self, statements: &[Stmt]) -> Result<Rc<Function>, Vec<LoxError>> {
+        self.functions.push(FunctionState::new(
+            Function::new("<script>"),
+            FunctionKind::Script,
+        ));
+        for (i, stmt) in statements.iter().enumerate() {
+            match stmt {
+                Stmt::Expression(expr) if i + 1 == statements.len() => {
+                    self.step(stmt);
+                    self.expression(expr);
+                }
+                _ => {
+                    self.statement(stmt);
+                    if i + 1 == statements.len() {
+                        self.emit_op(OpCode::Nil);
+                    }
+                }
+            }
+        }
+        if statements.is_empty() {
+            self.emit_op(OpCode::Nil);
+        }
+        self.emit_op(OpCode::Return);
+        let state = self.functions.pop().expect("脚本函数不见了");
+        if self.errors.is_empty() {
+            Ok(Rc::new(state.function))
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        self.step(stmt);
+        match stmt {
+            Stmt::Expression(expr) => {
+                self.expression(expr);
+                self.emit_op(OpCode::Pop);
+            }
+            Stmt::Print { expr, .. } => {
+                self.expression(expr);
+                // 输出失败时报告在语句的位置，而不是表达式最后一个记号的位置
+                if let Some(token) = stmt.token() {
+                    self.locate(token);
+                }
+                self.emit_op(OpCode::Print);
+            }
+            Stmt::Var { name, initializer } => {
+                match initializer {
+                    Some(expr) => self.expression(expr),
+                    None => self.emit_op(OpCode::Uninitialized),
+                }
+                self.define_variable(name, initializer.is_none());
+            }
+            Stmt::Block { statements } => {
+                let (line, column) = stmt.token().map_or((0, 0), |t| (t.line, t.column));
+                let start = self.code_len();
+                let index = self.state().function.chunk.blocks.len();
+                self.state().function.chunk.blocks.push(Block {
+                    start,
+                    end: start,
+                    line,
+                    column,
+                });
+                self.begin_scope();
+                for stmt in statements {
+                    self.statement(stmt);
+                }
+                self.end_scope();
+                let end = self.code_len();
+                self.state().function.chunk.blocks[index].end = end;
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                self.statement(then_branch);
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump);
+                self.emit_op(OpCode::Pop);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                label,
+                ..
+            } => {
+                let loop_start = self.code_len();
+                self.expression(condition);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit_op(OpCode::Pop);
+                let locals = self.state().locals.len();
+                self.state().loops.push(Loop {
+                    label: label.as_ref().map(|l| l.lexeme.clone()),
+                    locals,
+                    breaks: Vec::new(),
+                    continues: Vec::new(),
+                });
+                self.statement(body);
+                let current = self.state().loops.pop().expect("循环不见了");
+                // continue 跳到循环体之后，先执行增量部分再回到条件
+                for jump in current.continues {
+                    self.patch_jump(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                    self.emit_op(OpCode::Pop);
+                }
+                self.emit_loop(loop_start);
+                self.patch_jump(exit_jump);
+                self.emit_op(OpCode::Pop);
+                for jump in current.breaks {
+                    self.patch_jump(jump);
+                }
+            }
+            Stmt::Break { label } => self.loop_jump(label, true),
+            Stmt::Continue { label } => self.loop_jump(label, false),
+            Stmt::Function(declaration) => {
+                // 先声明再编译函数体，函数体里才能递归调用自己
+                let global = self.declare_variable(&declaration.name, false);
+                self.function(declaration, FunctionKind::Function);
+                self.finish_definition(global);
+            }
+            Stmt::Return { value, .. } => {
+                match value {
+                    Some(expr) => self.expression(expr),
+                    None => self.emit_default_return(),
+                }
+                self.emit_op(OpCode::Return);
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class(name, superclass.as_ref(), methods),
+        }
+    }
+    // 每条语句开头计一步；有位置的语句同时记下位置，和树遍历解释器的 tick 对应
+    fn step(&mut self, stmt: &Stmt) {
+        match stmt.token() {
+            Some(token) => {
+                self.locate(token);
+                self.emit_op(OpCode::StepAt);
+            }
+            None => self.emit_op(OpCode::Step),
+        }
+    }
+    fn loop_jump(&mut self, label: &Option<Token>, is_break: bool) {
+        let target = label.as_ref().map(|l| &l.lexeme);
+        // 不带标签的作用于最内层循环；语法分析保证一定找得到
+        let Some(index) = self
+            .state_ref()
+            .loops
+            .iter()
+            .rposition(|l| target.is_none() || l.label.as_ref() == target)
+        else {
+            return;
+        };
+        let locals = self.state_ref().loops[index].locals;
+        self.discard_locals(locals);
+        let jump = self.emit_jump(OpCode::Jump);
+        let target = &mut self.state().loops[index];
+        if is_break {
+            target.breaks.push(jump);
+        } else {
+            target.continues.push(jump);
+        }
+    }
+    fn class(&mut self, name: &Token, superclass: Option<&Expr>, methods: &[Rc<FunctionDecl>]) {
+        let name_constant = self.identifier_constant(&name.lexeme);
+        // 局部的类先占好槽位，方法里可以通过上值引用类自己；全局的类最后再定义
+        let global = self.declare_variable(name, false);
+        if global.is_none() {
+            self.emit_op(OpCode::Nil);
+        }
+        if let Some(superclass) = superclass {
+            self.expression(superclass);
+            if let Some(token) = superclass.token() {
+                self.locate(token);
+            }
+            self.emit_op(OpCode::CheckSuperclass);
+            // 父类放在只有 super 的作用域里，方法通过上值引用它
+            self.begin_scope();
+            self.add_local("super", false);
+        }
+        for method in methods {
+            let kind = if method.name.lexeme == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind);
+        }
+        self.locate(name);
+        self.emit_op(OpCode::Class);
+        self.emit_u16(name_constant);
+        self.emit_u16(methods.len() as u16);
+        self.emit_byte(superclass.is_some() as u8);
+        match global {
+            Some(constant) => {
+                self.emit_op(OpCode::DefineGlobal);
+                self.emit_u16(constant);
+            }
+            None => {
+                let slot = self.resolve_local(self.functions.len() - 1, &name.lexeme);
+                if let Some((slot, _)) = slot {
+                    self.emit_op(OpCode::SetLocal);
+                    self.emit_u16(slot);
+                }
+                self.emit_op(OpCode::Pop);
+            }
+        }
+        if superclass.is_some() {
+            self.end_scope();
+        }
+        if methods.len() > u16::MAX as usize {
+            self.error("Too many methods in one class.");
+        }
+    }
+    fn function(&mut self, declaration: &FunctionDecl, kind: FunctionKind) {
+        let mut function = Function::new(declaration.name.lexeme.to_string());
+        function.arity = declaration.params.len();
+        function.is_initializer = kind == FunctionKind::Initializer;
+        self.functions.push(FunctionState::new(function, kind));
+        self.begin_scope();
+        for param in &declaration.params {
+            self.add_local(&param.lexeme, false);
+        }
+        for stmt in &declaration.body {
+            self.statement(stmt);
+        }
+        self.emit_default_return();
+        self.emit_op(OpCode::Return);
+        let state = self.functions.pop().expect("函数不见了");
+        let mut function = state.function;
+        function.upvalue_count = state.upvalues.len();
+        // 函数体编译完后位置停在最后一条语句，闭包指令算在声明处
+        self.locate(&declaration.name);
+        let constant = self.make_constant(Constant::Function(Rc::new(function)));
+        self.emit_op(OpCode::Closure);
+        self.emit_u16(constant);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_u16(upvalue.index);
+        }
+    }
+    // 没有 return 值时的返回值：init 返回 this，其它函数返回 nil
+    fn emit_default_return(&mut self) {
+        if self.state_ref().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal);
+            self.emit_u16(0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(value) => match value {
+                Object::Number(n) => self.emit_constant(Constant::Number(*n)),
+                Object::String(s) => self.emit_constant(Constant::String(s.clone())),
+                Object::Boolean(true) => self.emit_op(OpCode::True),
+                Object::Boolean(false) => self.emit_op(OpCode::False),
+                // 语法分析只产生数字、字符串、布尔值和 nil 字面量
+                _ => self.emit_op(OpCode::Nil),
+            },
+            Expr::Grouping(expr) => self.expression(expr),
+            Expr::Unary { operator, right } => {
+                self.expression(right);
+                self.locate(operator);
+                match operator.token_type {
+                    TokenType::Bang => self.emit_op(OpCode::Not),
+                    _ => self.emit_op(OpCode::Negate),
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                self.locate(operator);
+                let op = match operator.token_type {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::In => OpCode::In,
+                    // 语法分析不会产生其它二元运算符
+                    _ => OpCode::Subtract,
+                };
+                self.emit_op(op);
+            }
+            Expr::Variable { name, .. } => self.get_variable(name),
+            Expr::Assign { name, value, .. } => {
+                self.expression(value);
+                self.locate(name);
+                match self.resolve_variable(&name.lexeme) {
+                    Variable::Local(slot, _) => {
+                        self.emit_op(OpCode::SetLocal);
+                        self.emit_u16(slot);
+                    }
+                    Variable::Upvalue(index, _) => {
+                        self.emit_op(OpCode::SetUpvalue);
+                        self.emit_u16(index);
+                    }
+                    Variable::Global(constant) => {
+                        self.emit_op(OpCode::SetGlobal);
+                        self.emit_u16(constant);
+                    }
+                }
+            }
+            Expr::Logical {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                if operator.token_type == TokenType::Or {
+                    // 左边为真时跳过右边，左边的值就是结果
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump);
+                    self.emit_op(OpCode::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                } else {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit_op(OpCode::Pop);
+                    self.expression(right);
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => {
+                self.expression(callee);
+                for argument in arguments {
+                    self.expression(argument);
+                }
+                self.locate(paren);
+                self.emit_op(OpCode::Call);
+                // 语法分析限制了参数个数不超过 255
+                self.emit_byte(arguments.len() as u8);
+            }
+            Expr::Get { object, name } => {
+                self.expression(object);
+                let constant = self.identifier_constant(&name.lexeme);
+                self.locate(name);
+                self.emit_op(OpCode::GetProperty);
+                self.emit_u16(constant);
+            }
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expression(object);
+                let constant = self.identifier_constant(&name.lexeme);
+                self.locate(name);
+                self.emit_op(OpCode::CheckInstance);
+                self.emit_u16(constant);
+                self.expression(value);
+                self.locate(name);
+                self.emit_op(OpCode::SetProperty);
+                self.emit_u16(constant);
+            }
+            Expr::This { keyword, .. } => self.get_variable(keyword),
+            Expr::Super {
+                keyword, method, ..
+            } => {
+                let this = Token::new(
+                    TokenType::This,
+                    "this".to_string(),
+                    Object::NULL,
+                    keyword.line,
+                    keyword.column,
+                );
+                self.get_variable(&this);
+                self.get_variable(keyword);
+                let constant = self.identifier_constant(&method.lexeme);
+                self.locate(method);
+                self.emit_op(OpCode::GetSuper);
+                self.emit_u16(constant);
+            }
+            Expr::Interpolation(parts) => {
+                for part in parts {
+                    self.expression(part);
+                    if !matches!(part, Expr::Literal(Object::String(_))) {
+                        self.emit_op(OpCode::Stringify);
+                    }
+                }
+                self.emit_count(OpCode::Interpolate, parts.len(), "string interpolation");
+            }
+            Expr::List(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit_count(OpCode::List, elements.len(), "list literal");
+            }
+            Expr::Map { brace, entries } => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.locate(brace);
+                    self.emit_op(OpCode::CheckKey);
+                    self.expression(value);
+                }
+                self.emit_count(OpCode::Map, entries.len(), "map literal");
+            }
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.locate(bracket);
+                self.emit_op(OpCode::GetIndex);
+            }
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.locate(bracket);
+                self.emit_op(OpCode::SetIndex);
+            }
+        }
+    }
+    fn emit_count(&mut self, op: OpCode, count: usize, what: &str) {
+        if count > u16::MAX as usize {
+            self.error(&format!("Too many elements in {}.", what));
+        }
+        self.emit_op(op);
+        self.emit_u16(count as u16);
+    }
+
+    fn get_variable(&mut self, name: &Token) {
+        self.locate(name);
+        let (op, operand, uninitialized) = match self.resolve_variable(&name.lexeme) {
+            Variable::Local(slot, uninitialized) => (OpCode::GetLocal, slot, uninitialized),
+            Variable::Upvalue(index, uninitialized) => (OpCode::GetUpvalue, index, uninitialized),
+            // 全局变量每次读取都按名字检查
+            Variable::Global(constant) => (OpCode::GetGlobal, constant, false),
+        };
+        self.emit_op(op);
+        self.emit_u16(operand);
+        if uninitialized {
+            let constant = self.identifier_constant(&name.lexeme);
+            self.emit_op(OpCode::CheckInitialized);
+            self.emit_u16(constant);
+        }
+    }
+    fn resolve_variable(&mut self, name: &Symbol) -> Variable {
+        let current = self.functions.len() - 1;
+        if let Some((slot, uninitialized)) = self.resolve_local(current, name) {
+            return Variable::Local(slot, uninitialized);
+        }
+        if let Some((index, uninitialized)) = self.resolve_upvalue(current, name) {
+            return Variable::Upvalue(index, uninitialized);
+        }
+        Variable::Global(self.identifier_constant(name))
+    }
+    fn resolve_local(&self, function: usize, name: &str) -> Option<(u16, bool)> {
+        let locals = &self.functions[function].locals;
+        // 顶层脚本的槽位 0 不是变量，函数的槽位 0 名字为空，都不会和变量重名
+        locals
+            .iter()
+            .rposition(|local| local.name == name && !local.name.is_empty())
+            .map(|slot| (slot as u16, locals[slot].uninitialized))
+    }
+    // 在外层函数里找变量，沿途每一层函数都记下这个上值
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<(u16, bool)> {
+        if function == 0 {
+            return None;
+        }
+        if let Some((slot, uninitialized)) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            return Some((
+                self.add_upvalue(function, slot, true, uninitialized),
+                uninitialized,
+            ));
+        }
+        let (index, uninitialized) = self.resolve_upvalue(function - 1, name)?;
+        Some((
+            self.add_upvalue(function, index, false, uninitialized),
+            uninitialized,
+        ))
+    }
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        index: u16,
+        is_local: bool,
+        uninitialized: bool,
+    ) -> u16 {
+        let upvalue = Upvalue {
+            index,
+            is_local,
+            uninitialized,
+        };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u16;
+        }
+        if upvalues.len() > u16::MAX as usize {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u16
+    }
+
+    // 声明变量：顶层的是全局变量，返回名字常量；局部变量放进当前作用域，返回 None
+    fn declare_variable(&mut self, name: &Token, uninitialized: bool) -> Option<u16> {
+        if self.state_ref().scope_depth == 0 {
+            return Some(self.identifier_constant(&name.lexeme));
+        }
+        self.add_local(&name.lexeme, uninitialized);
+        None
+    }
+    // 值已经在栈顶：全局变量按名字定义，局部变量就留在自己的槽位里
+    fn finish_definition(&mut self, global: Option<u16>) {
+        if let Some(constant) = global {
+            self.emit_op(OpCode::DefineGlobal);
+            self.emit_u16(constant);
+        }
+    }
+    fn define_variable(&mut self, name: &Token, uninitialized: bool) {
+        let global = self.declare_variable(name, uninitialized);
+        self.finish_definition(global);
+    }
+    fn add_local(&mut self, name: &str, uninitialized: bool) {
+        if self.state_ref().locals.len() > u16::MAX as usize {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        let depth = self.state_ref().scope_depth;
+        self.state().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            captured: false,
+            uninitialized,
+        });
+    }
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        let keep = state
+            .locals
+            .iter()
+            .rposition(|l| l.depth <= depth)
+            .map_or(0, |i| i + 1);
+        self.discard_locals(keep);
+        self.state().locals.truncate(keep);
+    }
+    // 弹出槽位在 keep 及以上的局部变量，被捕获的要关闭上值；不修改编译时的局部变量表
+    fn discard_locals(&mut self, keep: usize) {
+        for i in (keep..self.state_ref().locals.len()).rev() {
+            if self.state_ref().locals[i].captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("没有正在编译的函数")
+    }
+    fn state_ref(&self) -> &FunctionState {
+        self.functions.last().expect("没有正在编译的函数")
+    }
+    fn code_len(&self) -> usize {
+        self.state_ref().function.chunk.code.len()
+    }
+    fn locate(&mut self, token: &Token) {
+        self.line = token.line;
+        self.column = token.column;
+    }
+    fn emit_byte(&mut self, byte: u8) {
+        let (line, column) = (self.line, self.column);
+        self.state().function.chunk.write(byte, line, column);
+    }
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+    fn emit_u16(&mut self, value: u16) {
+        for byte in value.to_le_bytes() {
+            self.emit_byte(byte);
+        }
+    }
+    fn emit_constant(&mut self, constant: Constant) {
+        let index = self.make_constant(constant);
+        self.emit_op(OpCode::Constant);
+        self.emit_u16(index);
+    }
+    fn identifier_constant(&mut self, name: &Symbol) -> u16 {
+        self.make_constant(Constant::String(name.clone()))
+    }
+    fn make_constant(&mut self, constant: Constant) -> u16 {
+        let key = match &constant {
+            Constant::Number(n) => Some(ConstantKey::Number(n.to_bits())),
+            Constant::String(s) => Some(ConstantKey::String(s.clone())),
+            Constant::Function(_) => None,
+        };
+        if let Some(key) = &key
+            && let Some(&index) = self.state_ref().constants.get(key)
+        {
+            return index;
+        }
+        if self.state_ref().function.chunk.constants.len() > u16::MAX as usize {
+            self.error("Too many constants in one chunk.");
+            return 0;
+        }
+        let index = self.state().function.chunk.add_constant(constant) as u16;
+        if let Some(key) = key {
+            self.state().constants.insert(key, index);
+        }
+        index
+    }
+    // 写入带两个字节占位距离的跳转指令，返回占位的位置
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_u16(u16::MAX);
+        self.code_len() - 2
+    }
+    fn patch_jump(&mut self, offset: usize) {
+        let distance = self.code_len() - offset - 2;
+        if distance > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+            return;
+        }
+        let bytes = (distance as u16).to_le_bytes();
+        let code = &mut self.state().function.chunk.code;
+        code[offset..offset + 2].copy_from_slice(&bytes);
+    }
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let distance = self.code_len() - loop_start + 2;
+        if distance > u16::MAX as usize {
+            self.error("Loop body too large.");
+        }
+        self.emit_u16(distance as u16);
+    }
+    fn error(&mut self, message: &str) {
+        let diagnostic = Diagnostic::error(Span::point(self.line, self.column), message);
+        self.errors.push(LoxError::Compile(diagnostic));
+    }
+}
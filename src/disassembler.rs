@@ -0,0 +1,194 @@
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, Constant, Function, OpCode};
+
+/// 把函数的字节码反汇编成 clox 风格的文本：每个函数以 `== 名字 ==` 开头，
+/// 每行是指令的偏移、源码行号（和上一条指令同一行时是 `|`）、指令名和操作数。
+/// 函数里定义的函数接在后面依次打印。
+///
+/// ```
+/// use jlox::{Compiler, Parser, Scanner, disassembler};
+///
+/// let (tokens, _) = Scanner::new().scan_all("print 1 + 2;".to_string());
+/// let statements = Parser::new(tokens).parse().unwrap();
+/// let script = Compiler::new().compile(&statements).unwrap();
+/// let text = disassembler::disassemble(&script);
+/// assert!(text.starts_with("== <script> ==\n"));
+/// assert!(text.contains("OP_CONSTANT             0 '1'\n"));
+/// assert!(text.contains("OP_ADD\n"));
+/// ```
+pub fn disassemble(function: &Function) -> String {
+    let mut out = String::new();
+    disassemble_function(function, &mut out);
+    out
+}
+fn disassemble_function(function: &Function, out: &mut String) {
+    out.push_str(&disassemble_chunk(&function.chunk, &function.name));
+    for constant in &function.chunk.constants {
+        if let Constant::Function(inner) = constant {
+            out.push('\n');
+            disassemble_function(inner, out);
+        }
+    }
+}
+
+/// 只反汇编一段字节码，不展开其中的函数。
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut out = format!("== {} ==\n", name);
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, &mut out);
+    }
+    out
+}
+
+/// 反汇编 offset 处的一条指令，追加到 out，返回下一条指令的偏移。
+/// 字节码损坏（未知指令、操作数不完整、常量下标越界）时照样输出能看懂的内容，不会 panic。
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let _ = write!(out, "{:04} ", offset);
+    let (line, _) = chunk.position(offset);
+    if offset > 0 && chunk.position(offset - 1).0 == line {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{:4} ", line);
+    }
+    let byte = chunk.code[offset];
+    let Some(op) = OpCode::from_byte(byte) else {
+        let _ = writeln!(out, "Unknown opcode {}", byte);
+        return offset + 1;
+    };
+    let name = op_name(op);
+    // 操作数不完整时没法继续解码，剩下的字节都不再当作指令
+    let operand = |at: usize| -> Option<u16> {
+        let bytes = chunk.code.get(at..at + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    };
+    let truncated = |out: &mut String| {
+        let _ = writeln!(out, "{:<20} <truncated>", name);
+        chunk.code.len()
+    };
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::CheckInitialized
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::CheckInstance
+        | OpCode::GetSuper => {
+            let Some(index) = operand(offset + 1) else {
+                return truncated(out);
+            };
+            let _ = writeln!(out, "{:<20} {:4} {}", name, index, constant(chunk, index));
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::List
+        | OpCode::Map
+        | OpCode::Interpolate => {
+            let Some(value) = operand(offset + 1) else {
+                return truncated(out);
+            };
+            let _ = writeln!(out, "{:<20} {:4}", name, value);
+            offset + 3
+        }
+        OpCode::Call => {
+            let Some(count) = chunk.code.get(offset + 1) else {
+                return truncated(out);
+            };
+            let _ = writeln!(out, "{:<20} {:4}", name, count);
+            offset + 2
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+            let Some(distance) = operand(offset + 1) else {
+                return truncated(out);
+            };
+            let next = offset + 3;
+            let _ = match op {
+                OpCode::Loop => match next.checked_sub(distance as usize) {
+                    Some(target) => writeln!(out, "{:<20} {:4} -> {}", name, offset, target),
+                    None => writeln!(out, "{:<20} {:4} -> <invalid>", name, offset),
+                },
+                _ => writeln!(
+                    out,
+                    "{:<20} {:4} -> {}",
+                    name,
+                    offset,
+                    next + distance as usize
+                ),
+            };
+            next
+        }
+        OpCode::Closure => {
+            let Some(index) = operand(offset + 1) else {
+                return truncated(out);
+            };
+            let _ = writeln!(out, "{:<20} {:4} {}", name, index, constant(chunk, index));
+            let mut offset = offset + 3;
+            // 每个上值：是否是外层函数的局部变量，以及槽位或上值下标
+            let upvalue_count = match chunk.constants.get(index as usize) {
+                Some(Constant::Function(function)) => function.upvalue_count,
+                _ => 0,
+            };
+            for _ in 0..upvalue_count {
+                let (Some(&is_local), Some(index)) = (chunk.code.get(offset), operand(offset + 1))
+                else {
+                    return truncated(out);
+                };
+                let kind = if is_local == 1 { "local" } else { "upvalue" };
+                let _ = writeln!(out, "{:04}    | {:<20} {} {}", offset, "", kind, index);
+                offset += 3;
+            }
+            offset
+        }
+        OpCode::Class => {
+            let (Some(index), Some(count), Some(&inherits)) = (
+                operand(offset + 1),
+                operand(offset + 3),
+                chunk.code.get(offset + 5),
+            ) else {
+                return truncated(out);
+            };
+            let inherits = if inherits == 1 { ", inherits" } else { "" };
+            let _ = writeln!(
+                out,
+                "{:<20} {:4} {} ({} methods{})",
+                name,
+                index,
+                constant(chunk, index),
+                count,
+                inherits
+            );
+            offset + 6
+        }
+        _ => {
+            let _ = writeln!(out, "{}", name);
+            offset + 1
+        }
+    }
+}
+
+// 指令名写成 clox 的样子：GetLocal -> OP_GET_LOCAL
+fn op_name(op: OpCode) -> String {
+    let mut name = "OP".to_string();
+    for c in format!("{:?}", op).chars() {
+        if c.is_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+fn constant(chunk: &Chunk, index: u16) -> String {
+    match chunk.constants.get(index as usize) {
+        Some(Constant::Number(n)) => format!("'{}'", n),
+        Some(Constant::String(s)) => format!("'{}'", s),
+        Some(Constant::Function(function)) => format!("'<fn {}>'", function.name),
+        None => "<invalid constant>".to_string(),
+    }
+}
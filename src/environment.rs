@@ -1,63 +1,143 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    interpreter::RuntimeError,
+    interpreter::{RuntimeError, RuntimeErrorKind},
+    memory::{self, Garbage},
+    slot::Slot,
+    symbol::{Symbol, SymbolMap},
     token::{Object, Token},
 };
-#[derive(Clone, Debug)]
+// 作用域链：内层通过 Rc 引用外层，不复制外层环境，赋值可以沿链传到定义变量的那一层
+#[derive(Debug)]
 pub struct Environment {
-    pub values: HashMap<String, Object>,
-    pub enclosing: Option<Rc<RefCell<Environment>>>, //
+    pub values: SymbolMap<Slot>,
+    pub enclosing: Option<Rc<RefCell<Environment>>>,
+}
+impl Drop for Environment {
+    // 外层环境交给 memory::release，很长的环境链也不会递归释放；变量的值由槽位自己处理
+    fn drop(&mut self) {
+        if let Some(enclosing) = self.enclosing.take() {
+            memory::release([Garbage::Environment(enclosing)]);
+        }
+    }
+}
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Environment {
     pub fn new() -> Self {
         Environment {
-            values: HashMap::new(),
+            values: SymbolMap::default(),
             enclosing: None,
         }
     }
     pub fn new_with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
         Environment {
-            values: HashMap::new(),
+            values: SymbolMap::default(),
             enclosing: Some(enclosing),
         }
     }
-    pub fn define(&mut self, name: String, value: Object) {
-        self.values.insert(name, value);
+    pub fn define(&mut self, name: Symbol, value: Object) {
+        self.values.insert(name, Slot::new(value));
     }
-    pub fn get(&self, name: Token) -> Result<Object, RuntimeError> {
+    pub fn get(&self, name: &Token) -> Result<Object, RuntimeError> {
         if let Some(value) = self.values.get(&name.lexeme) {
-            match value {
+            match value.get() {
                 Object::Uninitialized => Err(RuntimeError {
-                    message: format!("Variable '{}' must be initialized before use", name.lexeme),
+                    message: format!("Variable '{}' must be initialized before use.", name.lexeme),
                     line: name.line,
+                    column: name.column,
+                    kind: RuntimeErrorKind::Error,
+                    trace: Vec::new(),
                 }),
-                _ => Ok(value.clone()),
+                value => Ok(value),
             }
         } else if let Some(enclosing) = &self.enclosing {
             enclosing.borrow().get(name)
         } else {
             Err(RuntimeError {
-                message: format!("Undefined variable '{}'", name.lexeme),
+                message: format!("Undefined variable '{}'.", name.lexeme),
                 line: name.line,
+                column: name.column,
+                kind: RuntimeErrorKind::Error,
+                trace: Vec::new(),
             })
         }
     }
-    pub fn assign(&mut self, name: Token, value: Object) -> Result<Object, RuntimeError> {
+    pub fn assign(&mut self, name: &Token, value: Object) -> Result<Object, RuntimeError> {
         // println!("Debug - Trying to assign: {} = {:?}", name.lexeme, value);
-        if self.values.contains_key(&name.lexeme) {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
             // println!("Debug - Found variable in current environment");
-            self.values.insert(name.lexeme.clone(), value.clone());
-            // println!("Debug - After insert: {:?}", self.values);
+            *slot = Slot::new(value.clone());
             Ok(value)
         } else if let Some(enclosing) = &self.enclosing {
             // println!("Debug - Looking in enclosing environment");
             enclosing.borrow_mut().assign(name, value)
         } else {
             Err(RuntimeError {
-                message: format!("Undefined variable '{}'", name.lexeme),
+                message: format!("Undefined variable '{}'.", name.lexeme),
                 line: name.line,
+                column: name.column,
+                kind: RuntimeErrorKind::Error,
+                trace: Vec::new(),
             })
         }
     }
+    // resolver 已经算出变量在第几层外层环境，直接跳过去，不再逐层按名字查找
+    pub fn get_at(&self, distance: usize, name: &Token) -> Result<Object, RuntimeError> {
+        if distance == 0 {
+            match self.values.get(&name.lexeme).map(Slot::get) {
+                Some(Object::Uninitialized) => Err(RuntimeError {
+                    message: format!("Variable '{}' must be initialized before use.", name.lexeme),
+                    line: name.line,
+                    column: name.column,
+                    kind: RuntimeErrorKind::Error,
+                    trace: Vec::new(),
+                }),
+                Some(value) => Ok(value),
+                None => Err(RuntimeError {
+                    message: format!("Undefined variable '{}'.", name.lexeme),
+                    line: name.line,
+                    column: name.column,
+                    kind: RuntimeErrorKind::Error,
+                    trace: Vec::new(),
+                }),
+            }
+        } else {
+            self.ancestor(distance).borrow().get_at(0, name)
+        }
+    }
+    pub fn assign_at(
+        &mut self,
+        distance: usize,
+        name: &Token,
+        value: Object,
+    ) -> Result<Object, RuntimeError> {
+        if distance == 0 {
+            self.values
+                .insert(name.lexeme.clone(), Slot::new(value.clone()));
+            Ok(value)
+        } else {
+            self.ancestor(distance)
+                .borrow_mut()
+                .assign_at(0, name, value)
+        }
+    }
+    fn ancestor(&self, distance: usize) -> Rc<RefCell<Environment>> {
+        let mut environment = self
+            .enclosing
+            .clone()
+            .expect("resolver 计算的作用域距离超出了环境链");
+        for _ in 1..distance {
+            let enclosing = environment
+                .borrow()
+                .enclosing
+                .clone()
+                .expect("resolver 计算的作用域距离超出了环境链");
+            environment = enclosing;
+        }
+        environment
+    }
 }
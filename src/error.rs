@@ -0,0 +1,182 @@
+use std::fmt;
+
+use crate::{
+    interpreter::{Frame, RuntimeError, RuntimeErrorKind},
+    parser::ParseError,
+    token::Token,
+    token_type::TokenType,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// 源码中的一段位置，行列都从 1 开始，结束位置包含在内。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+impl Span {
+    pub fn point(line: usize, column: usize) -> Self {
+        Span {
+            line,
+            column,
+            end_line: line,
+            end_column: column,
+        }
+    }
+    pub fn token(token: &Token) -> Self {
+        let len = token.lexeme.chars().count().max(1);
+        Span {
+            line: token.line,
+            column: token.column,
+            end_line: token.line,
+            end_column: token.column + len - 1,
+        }
+    }
+}
+
+/// jlox 的习惯：语法和静态检查错误报告在某个记号上，输出成 `Error at 'x'` 或 `Error at end`。
+#[derive(Debug, Clone, PartialEq)]
+pub struct At {
+    pub line: usize,
+    /// 记号的原文；None 表示文件末尾
+    pub lexeme: Option<String>,
+}
+impl At {
+    pub fn token(token: &Token) -> Self {
+        let lexeme = match token.token_type {
+            TokenType::Eof => None,
+            // 字符串记号里存的是内容，原文还带着引号
+            TokenType::String => Some(format!("\"{}\"", token.lexeme)),
+            _ => Some(token.lexeme.to_string()),
+        };
+        At {
+            line: token.line,
+            lexeme,
+        }
+    }
+}
+impl fmt::Display for At {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.lexeme {
+            Some(lexeme) => write!(f, "'{}'", lexeme),
+            None => write!(f, "end"),
+        }
+    }
+}
+
+/// 一条诊断信息。file 在单独执行一段源码时为空。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: Option<String>,
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+    /// 报告错误的记号，只有语法和静态检查错误有
+    pub at: Option<At>,
+    /// 运行时错误发生时的调用栈，最外层在前；其它阶段的错误为空
+    pub trace: Vec<Frame>,
+}
+impl Diagnostic {
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            file: None,
+            span,
+            severity: Severity::Error,
+            message: message.into(),
+            at: None,
+            trace: Vec::new(),
+        }
+    }
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(span, message)
+        }
+    }
+}
+
+/// 各个阶段产生的错误，按阶段区分。
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoxError {
+    Lexical(Diagnostic),
+    Syntax(Diagnostic),
+    Resolution(Diagnostic),
+    /// 编译成字节码时超出了指令格式的限制，例如常量太多
+    Compile(Diagnostic),
+    Runtime(Diagnostic),
+    /// 宿主设置的执行限制被触发，执行被中止
+    Limit(RuntimeErrorKind, Diagnostic),
+}
+impl LoxError {
+    pub fn diagnostic(&self) -> &Diagnostic {
+        match self {
+            LoxError::Lexical(d)
+            | LoxError::Syntax(d)
+            | LoxError::Resolution(d)
+            | LoxError::Compile(d)
+            | LoxError::Runtime(d)
+            | LoxError::Limit(_, d) => d,
+        }
+    }
+    pub fn diagnostic_mut(&mut self) -> &mut Diagnostic {
+        match self {
+            LoxError::Lexical(d)
+            | LoxError::Syntax(d)
+            | LoxError::Resolution(d)
+            | LoxError::Compile(d)
+            | LoxError::Runtime(d)
+            | LoxError::Limit(_, d) => d,
+        }
+    }
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LoxError::Lexical(_) => "Lexical error",
+            LoxError::Syntax(_) => "Syntax error",
+            LoxError::Resolution(_) => "Resolution error",
+            LoxError::Compile(_) => "Compile error",
+            LoxError::Runtime(_) => "Runtime error",
+            LoxError::Limit(..) => "Execution aborted",
+        }
+    }
+}
+impl fmt::Display for LoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = self.diagnostic();
+        if let Some(file) = &d.file {
+            write!(f, "{}:", file)?;
+        }
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            d.span.line,
+            d.span.column,
+            self.kind(),
+            d.message
+        )
+    }
+}
+impl std::error::Error for LoxError {}
+impl From<RuntimeError> for LoxError {
+    fn from(e: RuntimeError) -> Self {
+        let mut diagnostic = Diagnostic::error(Span::point(e.line, e.column), e.message);
+        diagnostic.trace = e.trace;
+        match e.kind {
+            RuntimeErrorKind::Error => LoxError::Runtime(diagnostic),
+            kind => LoxError::Limit(kind, diagnostic),
+        }
+    }
+}
+impl From<ParseError> for LoxError {
+    fn from(e: ParseError) -> Self {
+        let mut diagnostic = Diagnostic::error(Span::point(e.line, e.column), e.message);
+        diagnostic.at = Some(e.at);
+        LoxError::Syntax(diagnostic)
+    }
+}
@@ -1,5 +1,11 @@
+use std::{cell::Cell, rc::Rc};
+
 use crate::token::{Object, Token};
 
+// 变量引用节点上记录的作用域距离，由 resolver 填写；None 表示全局变量。
+// 记在节点上而不是解释器的表里，语法树释放时一起释放
+pub type Depth = Cell<Option<usize>>;
+
 #[derive(Debug)]
 pub enum Expr {
     Binary {
@@ -13,8 +19,12 @@ pub enum Expr {
     },
     Grouping(Box<Expr>),
     Literal(Object),
-    Variable(Token),
+    Variable {
+        depth: Depth,
+        name: Token,
+    },
     Assign {
+        depth: Depth,
         name: Token,
         value: Box<Expr>,
     },
@@ -23,11 +33,77 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    Call {
+        callee: Box<Expr>,
+        paren: Token, // 右括号，用于报告运行时错误的位置
+        arguments: Vec<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
+    This {
+        depth: Depth,
+        keyword: Token,
+    },
+    Super {
+        depth: Depth,
+        keyword: Token,
+        method: Token,
+    },
+    // 字符串插值，各部分求值后转成字符串再拼接
+    Interpolation(Vec<Expr>),
+    List(Vec<Expr>),
+    Map {
+        brace: Token, // 左花括号，键不合法时用来报告位置
+        entries: Vec<(Expr, Expr)>,
+    },
+    Index {
+        object: Box<Expr>,
+        bracket: Token, // 右方括号，用于报告运行时错误的位置
+        index: Box<Expr>,
+    },
+    SetIndex {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+}
+impl Expr {
+    /// 表达式里最能代表它位置的记号，字面量等没有记号的返回 None。
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Expr::Binary { operator, .. }
+            | Expr::Unary { operator, .. }
+            | Expr::Logical { operator, .. } => Some(operator),
+            Expr::Variable { name, .. }
+            | Expr::Assign { name, .. }
+            | Expr::Get { name, .. }
+            | Expr::Set { name, .. } => Some(name),
+            Expr::This { keyword, .. } | Expr::Super { keyword, .. } => Some(keyword),
+            Expr::Call { paren, .. } => Some(paren),
+            Expr::Map { brace, .. } => Some(brace),
+            Expr::Index { bracket, .. } | Expr::SetIndex { bracket, .. } => Some(bracket),
+            Expr::Grouping(expr) => expr.token(),
+            Expr::Interpolation(parts) | Expr::List(parts) => parts.iter().find_map(Expr::token),
+            Expr::Literal(_) => None,
+        }
+    }
 }
 #[derive(Debug)]
 pub enum Stmt {
     Expression(Expr),
-    Print(Expr),
+    Print {
+        // 交互模式下回显表达式的值时没有 print 关键字
+        keyword: Option<Token>,
+        expr: Expr,
+    },
     Var {
         name: Token,
         initializer: Option<Expr>,
@@ -41,7 +117,50 @@ pub enum Stmt {
         else_branch: Option<Box<Stmt>>,
     },
     While {
+        // while 或 for 关键字，for 循环转换过来时也保留它的位置
+        keyword: Token,
         condition: Expr,
         body: Box<Stmt>,
+        // for 循环的增量部分单独存放，continue 之后也要执行
+        increment: Option<Box<Expr>>,
+        label: Option<Token>,
+    },
+    Break {
+        label: Option<Token>,
+    },
+    Continue {
+        label: Option<Token>,
     },
+    // 函数声明用 Rc 包装，运行时的 LoxFunction 直接共享这份语法树
+    Function(Rc<FunctionDecl>),
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Class {
+        name: Token,
+        superclass: Option<Expr>, // 总是 Expr::Variable
+        methods: Vec<Rc<FunctionDecl>>,
+    },
+}
+impl Stmt {
+    /// 语句里最能代表它位置的记号，找不到时返回 None（例如空的块）。
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Stmt::Expression(expr) => expr.token(),
+            Stmt::Print { keyword, expr } => keyword.as_ref().or_else(|| expr.token()),
+            Stmt::Var { name, .. } | Stmt::Class { name, .. } => Some(name),
+            Stmt::Block { statements } => statements.iter().find_map(Stmt::token),
+            Stmt::If { condition, .. } => condition.token(),
+            Stmt::While { keyword, .. } | Stmt::Return { keyword, .. } => Some(keyword),
+            Stmt::Break { label } | Stmt::Continue { label } => label.as_ref(),
+            Stmt::Function(declaration) => Some(&declaration.name),
+        }
+    }
+}
+#[derive(Debug)]
+pub struct FunctionDecl {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
 }
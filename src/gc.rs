@@ -0,0 +1,455 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    mem::{self, size_of},
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    environment::Environment,
+    lox_class::LoxClass,
+    lox_function::LoxFunction,
+    lox_instance::LoxInstance,
+    lox_map::LoxMap,
+    memory::MAP_ENTRY_BYTES,
+    report::Sink,
+    slot::Slot,
+    symbol::Symbol,
+    token::Object,
+    vm::{BoundMethod, Closure, Upvalue},
+};
+
+/// 垃圾回收的设置。
+#[derive(Clone)]
+pub struct GcConfig {
+    /// 第一次回收前可以分配的字节数。之后每次回收后，下一次的阈值是存活对象大小的两倍，
+    /// 但不小于这个值
+    pub threshold: usize,
+    /// 每次分配都回收，用来暴露回收器的问题
+    pub stress: bool,
+    /// 每次回收后把回收了多少对象和字节写到这里
+    pub log: Option<Sink>,
+}
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            threshold: 1024 * 1024,
+            stress: false,
+            log: None,
+        }
+    }
+}
+
+/// 一次回收的结果。字节数和 [`crate::memory`] 一样是估算值。
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub freed_objects: usize,
+    pub freed_bytes: usize,
+    pub live_bytes: usize,
+}
+
+/// 值仍然用 `Rc` 管理，引用计数归零的对象立即释放；这里只负责引用计数回收不了的环，
+/// 例如实例的字段里存着捕获了这个实例的闭包。
+///
+/// 可能出现在环里的可变容器（被函数捕获的环境、实例、列表、映射和上值）创建时用弱引用登记在这里。
+/// 回收时从登记的对象出发找出它们之间的引用关系：引用计数比堆内引用多的对象还被堆外引用着，
+/// 这些就是根——全局环境、当前的环境链、虚拟机的值栈，以及 Rust 代码里暂存的值。
+/// 从根出发标记能访问到的对象，剩下的都是只被垃圾引用的环，清空其中的容器就能把环拆开，
+/// 之后由引用计数释放。
+pub struct Heap {
+    tracked: Vec<Tracked>,
+    config: GcConfig,
+    // 上次回收之后分配的字节数，以及下次回收的阈值
+    allocated: usize,
+    next_gc: usize,
+}
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Heap {
+    pub fn new() -> Self {
+        Self::new_with_config(GcConfig::default())
+    }
+    pub fn new_with_config(config: GcConfig) -> Self {
+        Heap {
+            tracked: Vec::new(),
+            next_gc: config.threshold,
+            config,
+            allocated: 0,
+        }
+    }
+    pub fn set_config(&mut self, config: GcConfig) {
+        self.next_gc = config.threshold;
+        self.config = config;
+    }
+    pub fn config(&self) -> &GcConfig {
+        &self.config
+    }
+    /// 登记新创建的实例、列表或映射；其它值不会单独成环，不需要登记。
+    pub fn track(&mut self, object: &Object) {
+        let tracked = match object {
+            Object::Instance(instance) => Tracked::Instance(Rc::downgrade(instance)),
+            Object::List(list) => Tracked::List(Rc::downgrade(list)),
+            Object::Map(map) => Tracked::Map(Rc::downgrade(map)),
+            _ => return,
+        };
+        self.tracked.push(tracked);
+    }
+    /// 登记被函数捕获的环境。同一个环境里连续定义多个函数时只登记一次。
+    pub fn track_environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        if let Some(Tracked::Environment(last)) = self.tracked.last()
+            && Weak::as_ptr(last) == Rc::as_ptr(environment)
+        {
+            return;
+        }
+        self.tracked
+            .push(Tracked::Environment(Rc::downgrade(environment)));
+    }
+    pub fn track_upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.tracked.push(Tracked::Upvalue(Rc::downgrade(upvalue)));
+    }
+    /// 记下新分配的字节数，该回收时返回 true。
+    pub fn allocate(&mut self, bytes: usize) -> bool {
+        self.allocated += bytes;
+        self.config.stress || self.allocated >= self.next_gc
+    }
+    /// 立即回收一次，返回回收的结果。
+    pub fn collect(&mut self) -> GcStats {
+        let mut graph = Graph::default();
+        // 已经释放的对象的登记顺便清理掉
+        self.tracked.retain(|tracked| match tracked.upgrade() {
+            Some(node) => {
+                graph.add(node);
+                true
+            }
+            None => false,
+        });
+        graph.scan();
+        let marked = graph.mark();
+        let mut stats = GcStats::default();
+        for (node, marked) in graph.nodes.iter().zip(marked) {
+            let bytes = node.bytes();
+            if marked {
+                stats.live_bytes += bytes;
+            } else {
+                stats.freed_objects += 1;
+                stats.freed_bytes += bytes;
+                node.clear();
+            }
+        }
+        // 放掉图里的引用后，拆开的环上的对象随之释放
+        drop(graph);
+        self.allocated = 0;
+        self.next_gc = self.config.threshold.max(stats.live_bytes * 2);
+        stats
+    }
+    pub(crate) fn log(&self, stats: &GcStats) {
+        if let Some(log) = &self.config.log {
+            let _ = writeln!(
+                log.borrow_mut(),
+                "-- gc: freed {} objects ({} bytes), {} bytes live, next collection after {} bytes",
+                stats.freed_objects,
+                stats.freed_bytes,
+                stats.live_bytes,
+                self.next_gc
+            );
+        }
+    }
+}
+
+// 登记的对象
+enum Tracked {
+    Environment(Weak<RefCell<Environment>>),
+    Instance(Weak<RefCell<LoxInstance>>),
+    List(Weak<RefCell<Vec<Slot>>>),
+    Map(Weak<RefCell<LoxMap>>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+impl Tracked {
+    fn upgrade(&self) -> Option<Node> {
+        Some(match self {
+            Tracked::Environment(weak) => Node::Environment(weak.upgrade()?),
+            Tracked::Instance(weak) => Node::Instance(weak.upgrade()?),
+            Tracked::List(weak) => Node::List(weak.upgrade()?),
+            Tracked::Map(weak) => Node::Map(weak.upgrade()?),
+            Tracked::Upvalue(weak) => Node::Upvalue(weak.upgrade()?),
+        })
+    }
+}
+
+// 引用图里的一个对象，持有一份强引用
+enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Slot>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
+}
+impl Node {
+    fn of(object: &Object) -> Option<Node> {
+        Some(match object {
+            Object::Instance(instance) => Node::Instance(instance.clone()),
+            Object::List(list) => Node::List(list.clone()),
+            Object::Map(map) => Node::Map(map.clone()),
+            Object::Function(function) => Node::Function(function.clone()),
+            Object::Class(class) => Node::Class(class.clone()),
+            Object::Closure(closure) => Node::Closure(closure.clone()),
+            Object::BoundMethod(bound) => Node::BoundMethod(bound.clone()),
+            // 原生函数由宿主注册，引用了什么看不到，当作叶子；它引用的对象因此会被当作根
+            _ => return None,
+        })
+    }
+    fn ptr(&self) -> *const () {
+        match self {
+            Node::Environment(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Instance(rc) => Rc::as_ptr(rc) as *const (),
+            Node::List(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Map(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Upvalue(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Function(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Class(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Closure(rc) => Rc::as_ptr(rc) as *const (),
+            Node::BoundMethod(rc) => Rc::as_ptr(rc) as *const (),
+        }
+    }
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(rc) => Rc::strong_count(rc),
+            Node::Instance(rc) => Rc::strong_count(rc),
+            Node::List(rc) => Rc::strong_count(rc),
+            Node::Map(rc) => Rc::strong_count(rc),
+            Node::Upvalue(rc) => Rc::strong_count(rc),
+            Node::Function(rc) => Rc::strong_count(rc),
+            Node::Class(rc) => Rc::strong_count(rc),
+            Node::Closure(rc) => Rc::strong_count(rc),
+            Node::BoundMethod(rc) => Rc::strong_count(rc),
+        }
+    }
+    // 对象持有的每一份强引用都要报告一次，同一个对象被引用两次就报告两次。
+    // 正在被借用的容器看不到内容，返回 false
+    fn children(&self, visit: &mut impl FnMut(Node)) -> bool {
+        let mut object = |value: &Object| {
+            if let Some(node) = Node::of(value) {
+                visit(node);
+            }
+        };
+        match self {
+            Node::Environment(environment) => {
+                let Ok(environment) = environment.try_borrow() else {
+                    return false;
+                };
+                for value in environment.values.values() {
+                    value.with(&mut object);
+                }
+                if let Some(enclosing) = &environment.enclosing {
+                    visit(Node::Environment(enclosing.clone()));
+                }
+            }
+            Node::Instance(instance) => {
+                let Ok(instance) = instance.try_borrow() else {
+                    return false;
+                };
+                for value in instance.fields.values() {
+                    value.with(&mut object);
+                }
+                visit(Node::Class(instance.class.clone()));
+            }
+            Node::List(list) => {
+                let Ok(list) = list.try_borrow() else {
+                    return false;
+                };
+                for element in list.iter() {
+                    element.with(&mut object);
+                }
+            }
+            Node::Map(map) => {
+                let Ok(map) = map.try_borrow() else {
+                    return false;
+                };
+                // 每个键存了两份：条目里一份，索引表里一份
+                for (key, value) in map.entries() {
+                    object(key.object());
+                    object(key.object());
+                    value.with(&mut object);
+                }
+            }
+            Node::Upvalue(upvalue) => {
+                let Ok(upvalue) = upvalue.try_borrow() else {
+                    return false;
+                };
+                if let Upvalue::Closed(value) = &*upvalue {
+                    value.with(&mut object);
+                }
+            }
+            Node::Function(function) => visit(Node::Environment(function.closure.clone())),
+            Node::Class(class) => {
+                class.methods.values().for_each(&mut object);
+                if let Some(superclass) = &class.superclass {
+                    visit(Node::Class(superclass.clone()));
+                }
+            }
+            Node::Closure(closure) => {
+                for upvalue in &closure.upvalues {
+                    visit(Node::Upvalue(upvalue.clone()));
+                }
+            }
+            Node::BoundMethod(bound) => {
+                object(&bound.receiver);
+                visit(Node::Closure(bound.method.clone()));
+            }
+        }
+        true
+    }
+    // 对象自身的大致大小，估算方法和 memory 模块一致，字符串算在存放它的对象里
+    fn bytes(&self) -> usize {
+        fn slots<'a>(values: impl Iterator<Item = &'a Slot>) -> usize {
+            values
+                .map(|value| {
+                    size_of::<Slot>()
+                        + value.with(|value| match value {
+                            Object::String(s) => s.len(),
+                            _ => 0,
+                        })
+                })
+                .sum()
+        }
+        let names = |names: &mut dyn Iterator<Item = &Symbol>| -> usize {
+            names.map(|name| name.len()).sum()
+        };
+        match self {
+            Node::Environment(environment) => match environment.try_borrow() {
+                Ok(environment) => {
+                    size_of::<Environment>()
+                        + names(&mut environment.values.keys())
+                        + slots(environment.values.values())
+                }
+                Err(_) => size_of::<Environment>(),
+            },
+            Node::Instance(instance) => match instance.try_borrow() {
+                Ok(instance) => {
+                    size_of::<LoxInstance>()
+                        + names(&mut instance.fields.keys())
+                        + slots(instance.fields.values())
+                }
+                Err(_) => size_of::<LoxInstance>(),
+            },
+            Node::List(list) => match list.try_borrow() {
+                Ok(list) => {
+                    // 空出来的容量也算，每个槽位的大小已经算在 slots 里
+                    (list.capacity() - list.len()) * size_of::<Slot>() + slots(list.iter())
+                }
+                Err(_) => 0,
+            },
+            Node::Map(map) => match map.try_borrow() {
+                Ok(map) => map.entries().len() * MAP_ENTRY_BYTES,
+                Err(_) => 0,
+            },
+            Node::Upvalue(_) => size_of::<Upvalue>(),
+            Node::Function(_) => size_of::<LoxFunction>(),
+            Node::Class(class) => {
+                size_of::<LoxClass>()
+                    + class.name.capacity()
+                    + names(&mut class.methods.keys())
+                    + class.methods.len() * size_of::<Object>()
+            }
+            Node::Closure(closure) => {
+                size_of::<Closure>() + closure.upvalues.len() * size_of::<Rc<RefCell<Upvalue>>>()
+            }
+            Node::BoundMethod(_) => size_of::<BoundMethod>(),
+        }
+    }
+    // 清空垃圾容器的内容，拆开引用环
+    fn clear(&self) {
+        match self {
+            Node::Environment(environment) => {
+                if let Ok(mut environment) = environment.try_borrow_mut() {
+                    environment.values.clear();
+                    environment.enclosing = None;
+                }
+            }
+            Node::Instance(instance) => {
+                if let Ok(mut instance) = instance.try_borrow_mut() {
+                    instance.fields.clear();
+                }
+            }
+            Node::List(list) => {
+                if let Ok(mut list) = list.try_borrow_mut() {
+                    list.clear();
+                }
+            }
+            Node::Map(map) => {
+                if let Ok(mut map) = map.try_borrow_mut() {
+                    *map = LoxMap::new();
+                }
+            }
+            Node::Upvalue(upvalue) => {
+                if let Ok(mut upvalue) = upvalue.try_borrow_mut() {
+                    *upvalue = Upvalue::Closed(Slot::new(Object::NULL));
+                }
+            }
+            // 不可变的对象不会单独成环，环上一定有上面的某种容器
+            _ => {}
+        }
+    }
+}
+
+// 从登记的对象出发能访问到的所有对象，以及它们之间的引用
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    index: HashMap<*const (), usize>,
+    edges: Vec<Vec<usize>>,
+    // 被图里其它对象引用的次数
+    internal: Vec<usize>,
+    // 内容看不到的对象，只能当作根
+    opaque: Vec<bool>,
+}
+impl Graph {
+    fn add(&mut self, node: Node) -> usize {
+        let ptr = node.ptr();
+        if let Some(&i) = self.index.get(&ptr) {
+            return i;
+        }
+        self.index.insert(ptr, self.nodes.len());
+        self.nodes.push(node);
+        self.edges.push(Vec::new());
+        self.internal.push(0);
+        self.opaque.push(false);
+        self.nodes.len() - 1
+    }
+    // 逐个找出对象引用的对象，新发现的对象追加在后面继续找
+    fn scan(&mut self) {
+        let mut i = 0;
+        while i < self.nodes.len() {
+            let mut children = Vec::new();
+            let visible = self.nodes[i].children(&mut |child| children.push(child));
+            self.opaque[i] = !visible;
+            for child in children {
+                let j = self.add(child);
+                self.edges[i].push(j);
+                self.internal[j] += 1;
+            }
+            i += 1;
+        }
+    }
+    // 图本身对每个对象持有一份引用，除此之外的引用都在图里时，对象只被图里的对象引用
+    fn mark(&self) -> Vec<bool> {
+        let mut marked = vec![false; self.nodes.len()];
+        let mut pending: Vec<usize> = (0..self.nodes.len())
+            .filter(|&i| self.opaque[i] || self.nodes[i].strong_count() - 1 > self.internal[i])
+            .collect();
+        while let Some(i) = pending.pop() {
+            if mem::replace(&mut marked[i], true) {
+                continue;
+            }
+            pending.extend(self.edges[i].iter().filter(|&&j| !marked[j]));
+        }
+        marked
+    }
+}
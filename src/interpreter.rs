@@ -1,277 +1,1184 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
     environment::Environment,
-    expr::{Expr, Stmt},
-    token::Object,
+    expr::{Depth, Expr, FunctionDecl, Stmt},
+    gc::{GcConfig, GcStats, Heap},
+    lox_callable::{LoxCallable, NativeFunction},
+    lox_class::LoxClass,
+    lox_function::LoxFunction,
+    lox_instance::LoxInstance,
+    lox_map::{LoxMap, MapKey},
+    memory::{self, MAP_ENTRY_BYTES},
+    report::{self, Sink},
+    slot::Slot,
+    symbol::{Symbol, SymbolMap},
+    token::{Object, Token},
     token_type::TokenType,
 };
+/// 运行时错误的种类。除 Error 外都是宿主设置的限制被触发，执行被中止。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuntimeErrorKind {
+    Error,
+    StepLimit,
+    TimeLimit,
+    MemoryLimit,
+}
+/// 调用栈中的一帧：顶层脚本、块或者一次函数调用。行列号是这一帧当前执行到的位置，
+/// 对外层的帧来说就是发起调用的地方；原生函数的帧没有位置，行号为 0。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+    pub column: usize,
+}
+impl Frame {
+    pub fn new(name: impl Into<String>, file: Option<Rc<str>>) -> Self {
+        Frame {
+            name: name.into(),
+            file,
+            line: 0,
+            column: 0,
+        }
+    }
+}
 #[derive(Debug)]
 pub struct RuntimeError {
     pub message: String,
     pub line: usize,
+    pub column: usize,
+    pub kind: RuntimeErrorKind,
+    /// 出错时的调用栈，最外层在前
+    pub trace: Vec<Frame>,
+}
+impl RuntimeError {
+    /// 供原生函数使用：不知道调用位置，行列号留空，由解释器在调用处补上。
+    pub fn new(message: impl Into<String>) -> Self {
+        RuntimeError {
+            message: message.into(),
+            line: 0,
+            column: 0,
+            kind: RuntimeErrorKind::Error,
+            trace: Vec::new(),
+        }
+    }
+    // 出错位置在 token 处的普通运行时错误。不内联，构造错误的临时值不占用
+    // 解释器递归路径上每一层的栈帧
+    #[cold]
+    #[inline(never)]
+    pub(crate) fn at(token: &Token, message: impl Into<String>) -> Self {
+        RuntimeError {
+            message: message.into(),
+            line: token.line,
+            column: token.column,
+            kind: RuntimeErrorKind::Error,
+            trace: Vec::new(),
+        }
+    }
+}
+/// 宿主对一次执行设置的限制。除了调用深度，默认都不限制。超出时抛出对应种类的 [`RuntimeError`]。
+///
+/// ```
+/// use jlox::{Lox, LoxError, RuntimeErrorKind, interpreter::Limits};
+///
+/// let mut lox = Lox::new();
+/// lox.interpreter().set_limits(Limits {
+///     max_steps: Some(10_000),
+///     ..Limits::default()
+/// });
+/// let errors = lox.run_source("while (true) {}").unwrap_err();
+/// assert!(matches!(errors[0], LoxError::Limit(RuntimeErrorKind::StepLimit, _)));
+/// // 中止之后解释器还能继续使用，每次执行重新计数
+/// assert!(lox.run_source("print 1;").is_ok());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// 一次执行最多执行的语句数（循环每迭代一次，循环体都要重新计数）
+    pub max_steps: Option<u64>,
+    /// 一次执行最多花费的时间
+    pub time_limit: Option<Duration>,
+    /// 脚本能占用的大致内存上限（字节），见 [`Interpreter::memory_usage`]。
+    /// 只在分配累计到一定量时才检查，实际占用可能短暂超出上限的 1/16
+    pub max_memory: Option<usize>,
+    /// 函数调用的最大深度（顶层脚本算一层），超过时报 Stack overflow.，两种执行方式都一样。
+    /// 树遍历解释器每层调用都占用原生栈，默认的 [`DEFAULT_MAX_CALL_DEPTH`] 在 8 MB 的栈上
+    /// 够用（未优化的构建每层约 10 KB，函数体嵌套很深时更多），调大时要在栈更大的线程上运行（见 [`crate::STACK_SIZE`]）
+    pub max_call_depth: usize,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: None,
+            time_limit: None,
+            max_memory: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+}
+/// [`Limits::max_call_depth`] 的默认值。
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 500;
+// 取当前时间比计数慢得多，每执行这么多步才检查一次是否超时
+const TIME_CHECK_INTERVAL: u64 = 1024;
+// 把值转成字符串时，每拼出这么多字节计一次分配
+const STRINGIFY_CHARGE_BYTES: usize = 4096;
+
+// 语句执行时的非正常退出：运行时错误，return 语句带着返回值向外展开，
+// 或者 break/continue 带着可选的标签跳到对应的循环
+#[derive(Debug)]
+pub enum Unwind {
+    Error(RuntimeError),
+    Return(Object),
+    Break(Option<Symbol>),
+    Continue(Option<Symbol>),
+}
+impl From<RuntimeError> for Unwind {
+    fn from(e: RuntimeError) -> Self {
+        Unwind::Error(e)
+    }
 }
 pub struct Interpreter {
-    environment: Environment,
+    pub globals: Rc<RefCell<Environment>>,
+    // 当前作用域，和外层作用域、闭包之间通过 Rc 共享，不再整体复制
+    environment: Rc<RefCell<Environment>>,
+    // print 语句的输出目标
+    output: Sink,
+    limits: Limits,
+    // 本次执行已经执行的语句数，以及超时的时刻
+    steps: u64,
+    deadline: Option<Instant>,
+    // 调用栈，最内层在最后；每一帧记录自己执行到的位置，被中止时报告在最内层的位置
+    frames: Vec<Frame>,
+    // 正在进行的函数调用层数，不算块
+    depth: usize,
+    // 正在执行的源码所在的文件，函数定义时记下来
+    file: Option<Rc<str>>,
+    // 兼容模式：打印和变量初始化的行为与 jlox 一致
+    compat: bool,
+    // execute_block 暂时换下来的外层环境，统计内存时也要从这里出发
+    suspended: Vec<Rc<RefCell<Environment>>>,
+    // 上次统计的内存占用，以及之后新分配的字节数
+    live_bytes: usize,
+    allocated_bytes: usize,
+    // 回收引用环的垃圾回收器
+    heap: Heap,
+}
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl Interpreter {
     pub fn new() -> Self {
-        Interpreter {
-            environment: Environment::new(),
+        Self::new_with_output(report::stdout())
+    }
+    pub fn new_with_output(output: Sink) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let mut interpreter = Interpreter {
+            globals: globals.clone(),
+            environment: globals,
+            output,
+            limits: Limits::default(),
+            steps: 0,
+            deadline: None,
+            frames: Vec::new(),
+            depth: 0,
+            file: None,
+            compat: false,
+            suspended: Vec::new(),
+            live_bytes: 0,
+            allocated_bytes: 0,
+            heap: Heap::new(),
+        };
+        interpreter.define_native("clock", 0, |_args| {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| RuntimeError::new(e.to_string()))?;
+            Ok(Object::Number(now.as_secs_f64()))
+        });
+        interpreter
+    }
+    /// 把 Rust 闭包注册成全局的 Lox 函数，调用时和普通函数一样检查参数个数。
+    ///
+    /// ```
+    /// use jlox::{Lox, Object, RuntimeError};
+    ///
+    /// let mut lox = Lox::new();
+    /// lox.interpreter().define_native("double", 1, |args| match &args[0] {
+    ///     Object::Number(n) => Ok(Object::Number(n * 2.0)),
+    ///     _ => Err(RuntimeError::new("double() expects a number.")),
+    /// });
+    /// assert!(matches!(lox.run_source("double(21);"), Ok(Object::Number(n)) if n == 42.0));
+    /// assert!(lox.run_source("double(\"x\");").is_err());
+    /// assert!(lox.run_source("double(1, 2);").is_err());
+    /// ```
+    pub fn define_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Object]) -> Result<Object, RuntimeError> + 'static,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        self.globals
+            .borrow_mut()
+            .define(Symbol::from(name), Object::Callable(Rc::new(native)));
+    }
+    // resolver 没有算出距离的是全局变量
+    fn look_up_variable(&self, depth: &Depth, name: &Token) -> Result<Object, RuntimeError> {
+        match depth.get() {
+            Some(distance) => self.environment.borrow().get_at(distance, name),
+            None => self.globals.borrow().get(name),
         }
     }
+    /// 设置之后执行的源码所在的文件，用于调用栈。
+    pub fn set_file(&mut self, file: Option<&str>) {
+        self.file = file.map(Rc::from);
+    }
+    /// 兼容模式下 nil 打印成 `nil`，数字按 Java 的格式打印，没有初始值的变量是 nil。
+    pub fn set_compat(&mut self, compat: bool) {
+        self.compat = compat;
+    }
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+    /// 设置垃圾回收的阈值、压力测试模式和日志，见 [`GcConfig`]。
+    pub fn set_gc(&mut self, config: GcConfig) {
+        self.heap.set_config(config);
+    }
+    pub fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+    /// 登记新创建的实例、列表或映射，让垃圾回收器能找到它们所在的引用环，返回原来的值。
+    pub fn track(&mut self, object: Object) -> Object {
+        self.heap.track(&object);
+        object
+    }
+    /// 立即回收一次引用环，设置了日志时写一条记录。
+    pub fn collect_garbage(&mut self) -> GcStats {
+        let stats = self.heap.collect();
+        self.heap.log(&stats);
+        stats
+    }
+    /// 限制针对每次执行，上一次被中止不影响这一次：重新计数，重新计时。
+    pub fn restart_limits(&mut self) {
+        self.steps = 0;
+        self.deadline = self.limits.time_limit.map(|limit| Instant::now() + limit);
+    }
+    pub fn file(&self) -> Option<Rc<str>> {
+        self.file.clone()
+    }
+    pub fn compat(&self) -> bool {
+        self.compat
+    }
+    // 返回最后一条语句的值，嵌入方可以拿到表达式语句的结果
     pub fn interpret(&mut self, statements: Vec<Stmt>) -> Result<Object, RuntimeError> {
-        //怎样打印statements
-        // println!("statements: {:?}", statements);
+        self.restart_limits();
+        self.frames.push(Frame::new("<script>", self.file.clone()));
+        let mut last = Object::NULL;
+        let mut result = Ok(());
         for stmt in statements {
-            self.interpret_stmt(&stmt)?;
+            match self.interpret_stmt(&stmt) {
+                Ok(value) => last = value,
+                Err(Unwind::Error(mut e)) => {
+                    self.capture_trace(&mut e);
+                    result = Err(e);
+                    break;
+                }
+                // resolver 已经拒绝了顶层的 return，这里只是兜底
+                Err(Unwind::Return(_)) => break,
+                // 语法分析保证 break/continue 只出现在循环里
+                Err(Unwind::Break(_)) | Err(Unwind::Continue(_)) => unreachable!(),
+            }
         }
-        Ok(Object::NULL)
+        self.frames.pop();
+        result.map(|_| last)
     }
 
+    // 每种表达式的求值放在单独的方法里：树遍历解释器每层 Lox 调用都要经过这里，
+    // 全写在一个 match 里时未优化的构建每层要占几十 KB 原生栈
     pub fn interpret_expr(&mut self, expr: &Expr) -> Result<Object, RuntimeError> {
         match expr {
             Expr::Literal(value) => Ok(value.clone()),
-            Expr::Grouping(expr) => self.interpret_expr(&*expr),
-            Expr::Unary { operator, right } => {
-                let right = self.interpret_expr(&*right)?;
-                match operator.token_type {
-                    TokenType::Minus => {
-                        if let Object::Number(value) = right {
-                            Ok(Object::Number(-value))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be a number".to_string(),
-                                line: operator.line,
-                            })
-                        }
-                    }
-                    TokenType::Bang => Ok(Object::Boolean(!Self::is_truthy(&right))),
-                    _ => Err(RuntimeError {
-                        message: "Operands must be a number".to_string(),
-                        line: operator.line,
-                    }),
-                }
-            }
+            Expr::Grouping(expr) => self.interpret_expr(expr),
+            Expr::Unary { operator, right } => self.unary(operator, right),
             Expr::Binary {
                 left,
                 operator,
                 right,
-            } => {
-                let left = self.interpret_expr(&*left)?;
-                let right = self.interpret_expr(&*right)?;
-                match operator.token_type {
-                    TokenType::Plus => {
-                        // 处理数字相加或字符串连接
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Number(a + b))
-                        } else if let (Object::String(a), Object::String(b)) = (&left, &right) {
-                            Ok(Object::String(format!("{}{}", a, b)))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers or two strings".to_string(),
-                                line: operator.line,
-                            }) //"Operands must be two numbers or two strings"
-                        }
-                    }
-                    TokenType::Minus => {
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Number(a - b))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers".to_string(),
-                                line: operator.line,
-                            }) //"Operands must be two numbers"
-                        }
-                    }
-                    TokenType::Star => {
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Number(a * b))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers".to_string(),
-                                line: operator.line,
-                            }) //"Operands must be two numbers"
-                        }
-                    }
-                    TokenType::Slash => {
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Number(a / b))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers".to_string(),
-                                line: operator.line,
-                            }) //"Operands must be two numbers"
-                        }
-                    }
-                    TokenType::Greater => {
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Boolean(a > b))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers".to_string(),
-                                line: operator.line,
-                            }) //"Operands must be two numbers"
-                        }
-                    }
-                    TokenType::GreaterEqual => {
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Boolean(a >= b))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers".to_string(),
-                                line: 0,
-                            }) //"Operands must be two numbers"
-                        }
-                    }
-                    TokenType::Less => {
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Boolean(a < b))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers".to_string(),
-                                line: operator.line,
-                            }) //"Operands must be two numbers"
-                        }
-                    }
-                    TokenType::LessEqual => {
-                        if let (Object::Number(a), Object::Number(b)) = (&left, &right) {
-                            Ok(Object::Boolean(a <= b))
-                        } else {
-                            Err(RuntimeError {
-                                message: "Operands must be two numbers".to_string(),
-                                line: operator.line,
-                            }) //"Operands must be two numbers"
-                        }
-                    }
-                    TokenType::EqualEqual => Ok(Object::Boolean(Self::is_equal(&left, &right))),
-                    TokenType::BangEqual => Ok(Object::Boolean(!Self::is_equal(&left, &right))),
-                    _ => {
-                        Err(RuntimeError {
-                            message: "Operands must be two numbers".to_string(),
-                            line: operator.line,
-                        }) //"Operands must be two numbers"
-                    }
-                }
-            }
-            Expr::Variable(name) => self.environment.get(name.clone()),
-            Expr::Assign { name, value } => {
-                let value = self.interpret_expr(&*value)?;
-                // println!("Debug - Assignment: {} = {:?}", name.lexeme, value);
-                self.environment.assign(name.clone(), value)
-            }
+            } => self.binary(left, operator, right),
+            Expr::Variable { depth, name } => self.look_up_variable(depth, name),
+            Expr::Assign { depth, name, value } => self.assign(depth, name, value),
             Expr::Logical {
                 left,
                 operator,
                 right,
-            } => {
-                let left = self.interpret_expr(&*left)?;
-                if operator.token_type == TokenType::Or {
-                    if Self::is_truthy(&left) {
-                        return Ok(left);
-                    }
-                } else {
-                    if !Self::is_truthy(&left) {
-                        return Ok(left);
+            } => self.logical(left, operator, right),
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+            } => self.call(callee, paren, arguments),
+            Expr::Get { object, name } => self.get(object, name),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => self.set(object, name, value),
+            Expr::Interpolation(parts) => self.interpolate(parts),
+            Expr::List(elements) => self.list(elements),
+            Expr::Map { brace, entries } => self.map(brace, entries),
+            Expr::Index {
+                object,
+                bracket,
+                index,
+            } => self.index(object, bracket, index),
+            Expr::SetIndex {
+                object,
+                bracket,
+                index,
+                value,
+            } => self.set_index(object, bracket, index, value),
+            Expr::This { depth, keyword } => self.look_up_variable(depth, keyword),
+            Expr::Super {
+                depth,
+                keyword,
+                method,
+            } => self.super_method(depth, keyword, method),
+        }
+    }
+    fn unary(&mut self, operator: &Token, right: &Expr) -> Result<Object, RuntimeError> {
+        let right = self.interpret_expr(right)?;
+        match (&operator.token_type, right) {
+            (TokenType::Minus, Object::Number(value)) => Ok(Object::Number(-value)),
+            (TokenType::Bang, right) => Ok(Object::Boolean(!Self::is_truthy(&right))),
+            _ => Err(RuntimeError::at(operator, "Operand must be a number.")),
+        }
+    }
+    fn binary(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Object, RuntimeError> {
+        let left = self.interpret_expr(left)?;
+        let right = self.interpret_expr(right)?;
+        match (&operator.token_type, &left, &right) {
+            (TokenType::EqualEqual, _, _) => Ok(Object::Boolean(Self::is_equal(&left, &right))),
+            (TokenType::BangEqual, _, _) => Ok(Object::Boolean(!Self::is_equal(&left, &right))),
+            (TokenType::In, _, Object::Map(map)) => Self::contains(map, left, operator),
+            (TokenType::In, _, _) => Err(RuntimeError::at(
+                operator,
+                "Right operand of 'in' must be a map.",
+            )),
+            // 处理数字相加或字符串连接
+            (TokenType::Plus, Object::String(a), Object::String(b)) => self.concatenate(a, b),
+            (TokenType::Plus, Object::Number(a), Object::Number(b)) => Ok(Object::Number(a + b)),
+            (TokenType::Plus, _, _) => Err(RuntimeError::at(
+                operator,
+                "Operands must be two numbers or two strings.",
+            )),
+            (operator_type, Object::Number(a), Object::Number(b)) => {
+                let (a, b) = (*a, *b);
+                match operator_type {
+                    TokenType::Minus => Ok(Object::Number(a - b)),
+                    TokenType::Star => Ok(Object::Number(a * b)),
+                    TokenType::Slash => Ok(Object::Number(a / b)),
+                    TokenType::Greater => Ok(Object::Boolean(a > b)),
+                    TokenType::GreaterEqual => Ok(Object::Boolean(a >= b)),
+                    TokenType::Less => Ok(Object::Boolean(a < b)),
+                    TokenType::LessEqual => Ok(Object::Boolean(a <= b)),
+                    _ => Err(RuntimeError::at(operator, "Operands must be numbers.")),
+                }
+            }
+            _ => Err(RuntimeError::at(operator, "Operands must be numbers.")),
+        }
+    }
+    fn concatenate(&mut self, a: &Symbol, b: &Symbol) -> Result<Object, RuntimeError> {
+        self.allocate(a.len() + b.len())?;
+        Ok(Object::String(Symbol::from(format!("{}{}", a, b))))
+    }
+    fn contains(
+        map: &RefCell<LoxMap>,
+        key: Object,
+        operator: &Token,
+    ) -> Result<Object, RuntimeError> {
+        let key = Self::map_key(key, operator)?;
+        Ok(Object::Boolean(map.borrow().contains(&key)))
+    }
+    fn assign(
+        &mut self,
+        depth: &Depth,
+        name: &Token,
+        value: &Expr,
+    ) -> Result<Object, RuntimeError> {
+        let value = self.interpret_expr(value)?;
+        match depth.get() {
+            Some(distance) => self
+                .environment
+                .borrow_mut()
+                .assign_at(distance, name, value),
+            None => self.globals.borrow_mut().assign(name, value),
+        }
+    }
+    fn logical(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Object, RuntimeError> {
+        let left = self.interpret_expr(left)?;
+        if operator.token_type == TokenType::Or {
+            if Self::is_truthy(&left) {
+                return Ok(left);
+            }
+        } else if !Self::is_truthy(&left) {
+            return Ok(left);
+        }
+        self.interpret_expr(right)
+    }
+    fn call(
+        &mut self,
+        callee: &Expr,
+        paren: &Token,
+        arguments: &[Expr],
+    ) -> Result<Object, RuntimeError> {
+        let callee = self.interpret_expr(callee)?;
+        let mut args = Vec::new();
+        for argument in arguments {
+            args.push(self.interpret_expr(argument)?);
+        }
+        self.invoke(callee, paren, args)
+    }
+    // 检查参数个数和调用深度后进入被调用的函数；和求值参数分开，两边的临时值不叠在同一个栈帧里
+    fn invoke(
+        &mut self,
+        callee: Object,
+        paren: &Token,
+        args: Vec<Object>,
+    ) -> Result<Object, RuntimeError> {
+        let arity = match &callee {
+            Object::Function(function) => function.arity(),
+            Object::Callable(callable) => callable.arity(),
+            Object::Class(class) => class.arity(),
+            _ => {
+                return Err(RuntimeError::at(
+                    paren,
+                    "Can only call functions and classes.",
+                ));
+            }
+        };
+        if args.len() != arity {
+            return Err(RuntimeError::at(
+                paren,
+                format!("Expected {} arguments but got {}.", arity, args.len()),
+            ));
+        }
+        // 无限递归时报错，而不是耗尽原生栈让进程崩溃；顶层脚本和虚拟机里一样也算一帧
+        if self.depth + 1 >= self.limits.max_call_depth {
+            return Err(RuntimeError::at(paren, "Stack overflow."));
+        }
+        // 调用方这一帧停在调用处，被调用的函数另开一帧
+        if let Some(frame) = self.frames.last_mut() {
+            frame.line = paren.line;
+            frame.column = paren.column;
+        }
+        let frame = match &callee {
+            Object::Function(function) => Frame::new(function.name(), function.file.clone()),
+            Object::Callable(callable) => Frame::new(callable.name(), None),
+            Object::Class(class) => Frame::new(class.name.clone(), self.current_file()),
+            _ => unreachable!(),
+        };
+        self.frames.push(frame);
+        self.depth += 1;
+        let mut result = match callee {
+            Object::Function(function) => function.call(self, args),
+            Object::Callable(callable) => callable.call(self, args),
+            Object::Class(class) => class.call(self, args),
+            _ => unreachable!(),
+        };
+        self.depth -= 1;
+        if let Err(e) = &mut result {
+            self.capture_trace(e);
+            // 原生函数报错时不知道位置，算在调用处
+            if e.line == 0 {
+                e.line = paren.line;
+                e.column = paren.column;
+            }
+        }
+        self.frames.pop();
+        result
+    }
+    fn get(&mut self, object: &Expr, name: &Token) -> Result<Object, RuntimeError> {
+        match self.interpret_expr(object)? {
+            Object::Instance(instance) => LoxInstance::get(&instance, name),
+            Object::List(list) if name.lexeme == "length" => {
+                Ok(Object::Number(list.borrow().len() as f64))
+            }
+            Object::Map(map) if name.lexeme == "length" => {
+                Ok(Object::Number(map.borrow().entries().len() as f64))
+            }
+            // 按插入顺序返回所有键组成的新列表，用来遍历映射
+            Object::Map(map) if name.lexeme == "keys" => {
+                self.allocate(map.borrow().entries().len() * size_of::<Slot>())?;
+                let keys = map.borrow().keys();
+                Ok(self.track(Object::List(Rc::new(RefCell::new(keys)))))
+            }
+            _ => Err(RuntimeError::at(name, "Only instances have properties.")),
+        }
+    }
+    fn set(&mut self, object: &Expr, name: &Token, value: &Expr) -> Result<Object, RuntimeError> {
+        let Object::Instance(instance) = self.interpret_expr(object)? else {
+            return Err(RuntimeError::at(name, "Only instances have fields."));
+        };
+        let value = self.interpret_expr(value)?;
+        if !instance.borrow().fields.contains_key(&name.lexeme) {
+            self.allocate(name.lexeme.len() + size_of::<Slot>())?;
+        }
+        instance.borrow_mut().set(name, value.clone());
+        Ok(value)
+    }
+    fn interpolate(&mut self, parts: &[Expr]) -> Result<Object, RuntimeError> {
+        let mut result = String::new();
+        for part in parts {
+            let value = self.interpret_expr(part)?;
+            result.push_str(&self.stringify(&value)?);
+        }
+        self.allocate(result.len())?;
+        Ok(Object::String(Symbol::from(result)))
+    }
+    fn list(&mut self, elements: &[Expr]) -> Result<Object, RuntimeError> {
+        let mut list = Vec::new();
+        for element in elements {
+            list.push(Slot::new(self.interpret_expr(element)?));
+        }
+        self.allocate(list.len() * size_of::<Slot>())?;
+        Ok(self.track(Object::List(Rc::new(RefCell::new(list)))))
+    }
+    fn map(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<Object, RuntimeError> {
+        let mut map = LoxMap::new();
+        for (key, value) in entries {
+            let key = Self::map_key(self.interpret_expr(key)?, brace)?;
+            let value = self.interpret_expr(value)?;
+            map.set(key, value);
+        }
+        self.allocate(map.entries().len() * MAP_ENTRY_BYTES)?;
+        Ok(self.track(Object::Map(Rc::new(RefCell::new(map)))))
+    }
+    fn index(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        index: &Expr,
+    ) -> Result<Object, RuntimeError> {
+        let object = self.interpret_expr(object)?;
+        let index = self.interpret_expr(index)?;
+        match object {
+            Object::List(list) => {
+                let list = list.borrow();
+                let i = Self::list_index(&index, list.len())
+                    .map_err(|message| RuntimeError::at(bracket, message))?;
+                Ok(list[i].get())
+            }
+            Object::Map(map) => {
+                let key = Self::map_key(index, bracket)?;
+                let value = map.borrow().get(&key);
+                match value {
+                    Some(value) => Ok(value),
+                    None => {
+                        let key = self.stringify(key.object())?;
+                        Err(RuntimeError::at(
+                            bracket,
+                            format!("Undefined key '{}'.", key),
+                        ))
                     }
                 }
-                let right = self.interpret_expr(&*right)?;
-                Ok(right)
             }
+            _ => Err(RuntimeError::at(
+                bracket,
+                "Only lists and maps can be indexed.",
+            )),
         }
     }
-    pub fn interpret_stmt(&mut self, stmt: &Stmt) -> Result<Object, RuntimeError> {
-        match stmt {
-            Stmt::Expression(expr) => self.interpret_expr(&expr),
-            Stmt::Print(expr) => {
-                let e = self.interpret_expr(&expr)?;
-                println!("{}", Self::stringify(&e));
-                Ok(Object::NULL)
+    fn set_index(
+        &mut self,
+        object: &Expr,
+        bracket: &Token,
+        index: &Expr,
+        value: &Expr,
+    ) -> Result<Object, RuntimeError> {
+        let object = self.interpret_expr(object)?;
+        let index = self.interpret_expr(index)?;
+        let value = self.interpret_expr(value)?;
+        match object {
+            Object::List(list) => {
+                let mut list = list.borrow_mut();
+                let i = Self::list_index(&index, list.len())
+                    .map_err(|message| RuntimeError::at(bracket, message))?;
+                list[i] = Slot::new(value.clone());
+                Ok(value)
             }
-            Stmt::Var { name, initializer } => {
-                let value = match initializer {
-                    Some(expr) => self.interpret_expr(&expr)?,
-                    None => Object::Uninitialized,
-                };
-                self.environment.define(name.lexeme.clone(), value);
-                Ok(Object::NULL)
+            Object::Map(map) => {
+                let key = Self::map_key(index, bracket)?;
+                if !map.borrow().contains(&key) {
+                    self.allocate(MAP_ENTRY_BYTES)?;
+                }
+                map.borrow_mut().set(key, value.clone());
+                Ok(value)
             }
-            Stmt::Block { statements } => self.execute_block(
-                statements,
-                Environment::new_with_enclosing(Rc::new(RefCell::new(self.environment.clone()))),
-            ),
+            _ => Err(RuntimeError::at(
+                bracket,
+                "Only lists and maps can be indexed.",
+            )),
+        }
+    }
+    fn super_method(
+        &mut self,
+        depth: &Depth,
+        keyword: &Token,
+        method: &Token,
+    ) -> Result<Object, RuntimeError> {
+        // super 总是局部变量；没有经过 resolver 的语法树里查不到它
+        let Some(distance) = depth.get() else {
+            return Err(RuntimeError::at(keyword, "Undefined variable 'super'."));
+        };
+        let superclass = match self.environment.borrow().get_at(distance, keyword)? {
+            Object::Class(class) => class,
+            _ => unreachable!("super 总是绑定到类"),
+        };
+        // this 绑定在 super 所在环境的内层一层
+        let this = Token::new(
+            TokenType::This,
+            "this".to_string(),
+            Object::NULL,
+            keyword.line,
+            keyword.column,
+        );
+        let instance = match self.environment.borrow().get_at(distance - 1, &this)? {
+            Object::Instance(instance) => instance,
+            _ => unreachable!("this 总是绑定到实例"),
+        };
+        match superclass.find_method(&method.lexeme) {
+            Some(Object::Function(function)) => {
+                Ok(Object::Function(Rc::new(function.bind(instance))))
+            }
+            _ => Err(RuntimeError::at(
+                method,
+                format!("Undefined property '{}'.", method.lexeme),
+            )),
+        }
+    }
+    pub fn interpret_stmt(&mut self, stmt: &Stmt) -> Result<Object, Unwind> {
+        self.tick(stmt.token())?;
+        match stmt {
+            Stmt::Expression(expr) => self.interpret_expr(expr).map_err(Unwind::Error),
+            Stmt::Print { expr, .. } => self.print_statement(expr, stmt.token()),
+            Stmt::Var { name, initializer } => self.var_declaration(name, initializer.as_ref()),
+            Stmt::Block { statements } => self.block(statements),
             Stmt::If {
                 condition,
                 then_branch,
                 else_branch,
-            } => {
-                let condition = self.interpret_expr(&condition)?;
-                if Self::is_truthy(&condition) {
-                    self.interpret_stmt(&*then_branch)?;
-                } else if let Some(else_branch) = else_branch {
-                    self.interpret_stmt(&*else_branch)?;
+            } => self.if_statement(condition, then_branch, else_branch.as_deref()),
+            Stmt::While {
+                keyword: _,
+                condition,
+                body,
+                increment,
+                label,
+            } => self.while_loop(condition, body, increment.as_deref(), label.as_ref()),
+            Stmt::Break { label } => Err(Unwind::Break(label.as_ref().map(|l| l.lexeme.clone()))),
+            Stmt::Continue { label } => {
+                Err(Unwind::Continue(label.as_ref().map(|l| l.lexeme.clone())))
+            }
+            Stmt::Function(declaration) => self.function_declaration(declaration),
+            Stmt::Return { value, .. } => self.return_statement(value.as_ref()),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => self.class_declaration(name, superclass.as_ref(), methods),
+        }
+    }
+    fn return_statement(&mut self, value: Option<&Expr>) -> Result<Object, Unwind> {
+        let value = match value {
+            Some(expr) => self.interpret_expr(expr)?,
+            None => Object::NULL,
+        };
+        Err(Unwind::Return(value))
+    }
+    fn print_statement(&mut self, expr: &Expr, token: Option<&Token>) -> Result<Object, Unwind> {
+        let value = self.interpret_expr(expr)?;
+        let (line, column) = token.map_or((0, 0), |t| (t.line, t.column));
+        self.print(&value, line, column)?;
+        Ok(Object::NULL)
+    }
+    fn var_declaration(
+        &mut self,
+        name: &Token,
+        initializer: Option<&Expr>,
+    ) -> Result<Object, Unwind> {
+        let value = match initializer {
+            Some(expr) => self.interpret_expr(expr)?,
+            // jlox 里没有初始值的变量就是 nil
+            None if self.compat => Object::NULL,
+            None => Object::Uninitialized,
+        };
+        self.environment
+            .borrow_mut()
+            .define(name.lexeme.clone(), value);
+        Ok(Object::NULL)
+    }
+    fn if_statement(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Stmt,
+        else_branch: Option<&Stmt>,
+    ) -> Result<Object, Unwind> {
+        let condition = self.interpret_expr(condition)?;
+        if Self::is_truthy(&condition) {
+            self.interpret_stmt(then_branch)?;
+        } else if let Some(else_branch) = else_branch {
+            self.interpret_stmt(else_branch)?;
+        }
+        Ok(Object::NULL)
+    }
+    fn block(&mut self, statements: &[Stmt]) -> Result<Object, Unwind> {
+        self.frames.push(Frame::new("<block>", self.current_file()));
+        let mut result = self.execute_block(
+            statements,
+            Environment::new_with_enclosing(self.environment.clone()),
+        );
+        if let Err(Unwind::Error(e)) = &mut result {
+            self.capture_trace(e);
+        }
+        self.frames.pop();
+        result
+    }
+    fn while_loop(
+        &mut self,
+        condition: &Expr,
+        body: &Stmt,
+        increment: Option<&Expr>,
+        label: Option<&Token>,
+    ) -> Result<Object, Unwind> {
+        // 循环体如果是块，每次迭代都会新建一个作用域，外层变量通过 enclosing 链修改
+        let label = label.map(|l| &l.lexeme);
+        while Self::is_truthy(&self.interpret_expr(condition)?) {
+            match self.interpret_stmt(body) {
+                Ok(_) => {}
+                // 不带标签的 break/continue 作用于最内层循环，带标签的要匹配本循环
+                Err(Unwind::Break(target)) if target.is_none() || target.as_ref() == label => {
+                    break;
                 }
-                Ok(Object::NULL)
+                Err(Unwind::Continue(target)) if target.is_none() || target.as_ref() == label => {}
+                Err(e) => return Err(e),
             }
-            Stmt::While { condition, body } => {
-                while Self::is_truthy(&self.interpret_expr(&condition)?) {
-                    // println!("Debug - Before body execution: {:?}", self.environment);
-                    let _result = match &**body {
-                        Stmt::Block { statements } => {
-                            let mut last_result = Object::NULL;
-                            for stmt in statements {
-                                last_result = self.interpret_stmt(stmt)?;
-                            }
-                            Ok(last_result)
-                        }
-                        _ => self.interpret_stmt(&*body),
-                    }?;
-                    // println!("Debug - After body execution: {:?}", self.environment);
+            if let Some(increment) = increment {
+                self.interpret_expr(increment)?;
+            }
+        }
+        Ok(Object::NULL)
+    }
+    fn function_declaration(&mut self, declaration: &Rc<FunctionDecl>) -> Result<Object, Unwind> {
+        // 函数捕获声明时所在的环境，形成闭包；环境里又存着函数，这是最常见的引用环
+        self.allocate(size_of::<LoxFunction>())?;
+        self.heap.track_environment(&self.environment);
+        let function = LoxFunction::new(
+            declaration.clone(),
+            self.environment.clone(),
+            false,
+            self.current_file(),
+        );
+        self.environment.borrow_mut().define(
+            declaration.name.lexeme.clone(),
+            Object::Function(Rc::new(function)),
+        );
+        Ok(Object::NULL)
+    }
+    fn class_declaration(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expr>,
+        methods: &[Rc<FunctionDecl>],
+    ) -> Result<Object, Unwind> {
+        let superclass = match superclass {
+            Some(expr) => match self.interpret_expr(expr)? {
+                Object::Class(class) => Some(class),
+                _ => {
+                    let token = match expr {
+                        Expr::Variable { name, .. } => name,
+                        _ => name,
+                    };
+                    return Err(RuntimeError::at(token, "Superclass must be a class.").into());
                 }
-                Ok(Object::NULL)
+            },
+            None => None,
+        };
+        // 有父类时，方法的闭包外面再套一层定义了 super 的环境
+        let method_environment = match &superclass {
+            Some(superclass) => {
+                let mut environment = Environment::new_with_enclosing(self.environment.clone());
+                environment.define(Symbol::intern("super"), Object::Class(superclass.clone()));
+                Rc::new(RefCell::new(environment))
             }
+            None => self.environment.clone(),
+        };
+        self.allocate(size_of::<LoxClass>())?;
+        self.heap.track_environment(&method_environment);
+        let mut class_methods = SymbolMap::default();
+        for method in methods {
+            let function = LoxFunction::new(
+                method.clone(),
+                method_environment.clone(),
+                method.name.lexeme == "init",
+                self.current_file(),
+            );
+            class_methods.insert(
+                method.name.lexeme.clone(),
+                Object::Function(Rc::new(function)),
+            );
         }
+        let class = LoxClass::new(name.lexeme.to_string(), superclass, class_methods);
+        self.environment
+            .borrow_mut()
+            .define(name.lexeme.clone(), Object::Class(Rc::new(class)));
+        Ok(Object::NULL)
     }
-    fn execute_block(
+    /// 打印一个值；写不出去时报告在 print 语句的位置。
+    pub fn print(
+        &mut self,
+        value: &Object,
+        line: usize,
+        column: usize,
+    ) -> Result<(), RuntimeError> {
+        self.print_with(value, line, column, Self::memory_usage)
+    }
+    /// 和 [`Interpreter::print`] 一样，由调用方统计当前的内存占用，见 [`Interpreter::allocate_with`]。
+    pub fn print_with(
+        &mut self,
+        value: &Object,
+        line: usize,
+        column: usize,
+        memory_usage: impl Fn(&Self) -> usize,
+    ) -> Result<(), RuntimeError> {
+        let text = self.stringify_with(value, memory_usage)?;
+        writeln!(self.output.borrow_mut(), "{}", text).map_err(|e| RuntimeError {
+            message: format!("Failed to write output: {}", e),
+            line,
+            column,
+            kind: RuntimeErrorKind::Error,
+            trace: Vec::new(),
+        })
+    }
+    // 每执行一条语句计一步，当前帧停在这条语句上
+    fn tick(&mut self, token: Option<&Token>) -> Result<(), RuntimeError> {
+        if let Some(token) = token
+            && let Some(frame) = self.frames.last_mut()
+        {
+            frame.line = token.line;
+            frame.column = token.column;
+        }
+        self.step()
+    }
+    /// 计一步，检查是否超出步数和时间限制。
+    pub fn step(&mut self) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if let Some(max_steps) = self.limits.max_steps
+            && self.steps > max_steps
+        {
+            return Err(self.abort(
+                RuntimeErrorKind::StepLimit,
+                format!("Execution step limit of {} exceeded.", max_steps),
+            ));
+        }
+        if self.steps.is_multiple_of(TIME_CHECK_INTERVAL) {
+            return self.check_deadline();
+        }
+        Ok(())
+    }
+    // 超过时间限制时报错
+    fn check_deadline(&self) -> Result<(), RuntimeError> {
+        if let Some(deadline) = self.deadline
+            && Instant::now() >= deadline
+        {
+            let limit = self.limits.time_limit.unwrap_or_default();
+            return Err(self.abort(
+                RuntimeErrorKind::TimeLimit,
+                format!("Execution time limit of {:?} exceeded.", limit),
+            ));
+        }
+        Ok(())
+    }
+    /// 当前能访问到的所有值大致占用的字节数，包括全局变量和正在执行的函数里的局部变量。
+    pub fn memory_usage(&self) -> usize {
+        let roots = [&self.globals, &self.environment];
+        memory::reachable_bytes(roots.into_iter().chain(&self.suspended), [])
+    }
+    /// 记录一次分配。分配累计到阈值时回收一次引用环（见 [`Heap`]）。设置了内存上限时，累计分配较多后重新统计实际占用，
+    /// 加上这次分配会超出上限就报错。
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), RuntimeError> {
+        self.allocate_with(bytes, Self::memory_usage)
+    }
+    /// 和 [`Interpreter::allocate`] 一样，由调用方统计当前的内存占用，
+    /// 供字节码虚拟机加上栈上的值。
+    pub fn allocate_with(
+        &mut self,
+        bytes: usize,
+        memory_usage: impl FnOnce(&Self) -> usize,
+    ) -> Result<(), RuntimeError> {
+        if self.heap.allocate(bytes) {
+            self.collect_garbage();
+        }
+        let Some(max_memory) = self.limits.max_memory else {
+            return Ok(());
+        };
+        self.allocated_bytes += bytes;
+        // 离上限还远时不统计；至少攒够上限的 1/16 再统计，避免接近上限时每次分配都遍历一遍
+        let headroom = max_memory.saturating_sub(self.live_bytes);
+        if self.allocated_bytes < headroom.max(max_memory / 16) {
+            return Ok(());
+        }
+        self.live_bytes = memory_usage(self);
+        self.allocated_bytes = 0;
+        if self.live_bytes + bytes > max_memory {
+            return Err(self.abort(
+                RuntimeErrorKind::MemoryLimit,
+                format!("Memory limit of {} bytes exceeded.", max_memory),
+            ));
+        }
+        Ok(())
+    }
+    fn abort(&self, kind: RuntimeErrorKind, message: String) -> RuntimeError {
+        let (line, column) = match self.frames.last() {
+            Some(frame) => (frame.line, frame.column),
+            None => (0, 0),
+        };
+        RuntimeError {
+            message,
+            line,
+            column,
+            kind,
+            trace: Vec::new(),
+        }
+    }
+    // 当前帧所在的文件；块和类的帧沿用外层的文件
+    fn current_file(&self) -> Option<Rc<str>> {
+        match self.frames.last() {
+            Some(frame) => frame.file.clone(),
+            None => self.file.clone(),
+        }
+    }
+    // 错误第一次离开某一帧时记下当时的调用栈，外层的帧不再覆盖；
+    // 最内层的帧停在出错的位置
+    fn capture_trace(&self, error: &mut RuntimeError) {
+        if !error.trace.is_empty() {
+            return;
+        }
+        error.trace = self.frames.clone();
+        if let Some(frame) = error.trace.last_mut()
+            && error.line != 0
+        {
+            frame.line = error.line;
+            frame.column = error.column;
+        }
+    }
+    pub fn execute_block(
         &mut self,
         statements: &[Stmt], // 改为切片引用
         environment: Environment,
-    ) -> Result<Object, RuntimeError> {
-        let previous = std::mem::replace(&mut self.environment, environment);
+    ) -> Result<Object, Unwind> {
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        self.suspended.push(previous);
         let result = (|| {
             for stmt in statements {
-                self.interpret_stmt(&stmt)?;
+                self.interpret_stmt(stmt)?;
             }
             Ok(Object::NULL)
         })(); // 立即执行闭包
-        self.environment = previous; // 无论如何都会恢复
+        // 无论如何都会恢复
+        self.environment = self
+            .suspended
+            .pop()
+            .expect("execute_block 换下的环境不见了");
         result
     }
     // 辅助函数：判断一个值是否为真
-    fn is_truthy(obj: &Object) -> bool {
+    pub fn is_truthy(obj: &Object) -> bool {
         match obj {
             Object::NULL => false,
             Object::Boolean(b) => *b,
             _ => true,
         }
     }
-    fn is_equal(a: &Object, b: &Object) -> bool {
+    pub fn is_equal(a: &Object, b: &Object) -> bool {
         match (a, b) {
             (Object::Number(a), Object::Number(b)) => a == b,
             (Object::String(a), Object::String(b)) => a == b,
             (Object::Boolean(a), Object::Boolean(b)) => a == b,
             (Object::NULL, Object::NULL) => true,
+            // 函数、类和实例按引用比较
+            (Object::Function(a), Object::Function(b)) => Rc::ptr_eq(a, b),
+            (Object::Closure(a), Object::Closure(b)) => Rc::ptr_eq(a, b),
+            (Object::BoundMethod(a), Object::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Object::Callable(a), Object::Callable(b)) => Rc::ptr_eq(a, b),
+            (Object::Class(a), Object::Class(b)) => Rc::ptr_eq(a, b),
+            (Object::Instance(a), Object::Instance(b)) => Rc::ptr_eq(a, b),
+            (Object::List(a), Object::List(b)) => Rc::ptr_eq(a, b),
+            (Object::Map(a), Object::Map(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
 
-    fn stringify(obj: &Object) -> String {
+    /// 下标必须是整数，并且落在 [0, len) 范围内，否则返回错误信息。
+    pub fn list_index(index: &Object, len: usize) -> Result<usize, String> {
+        let index = match index {
+            Object::Number(n) if n.fract() == 0.0 => *n,
+            _ => return Err("List index must be an integer.".to_string()),
+        };
+        if index < 0.0 || index >= len as f64 {
+            return Err(format!(
+                "Index {} out of bounds for list of length {}.",
+                index, len
+            ));
+        }
+        Ok(index as usize)
+    }
+
+    fn map_key(key: Object, token: &Token) -> Result<MapKey, RuntimeError> {
+        MapKey::new(key).map_err(|message| RuntimeError {
+            message,
+            line: token.line,
+            column: token.column,
+            kind: RuntimeErrorKind::Error,
+            trace: Vec::new(),
+        })
+    }
+
+    // jlox 用 Java 的 Double.toString 再去掉结尾的 ".0"：
+    // 绝对值在 [1e-3, 1e7) 之外时用科学计数法，例如 1.0E7、1.5E-4
+    fn java_number(value: f64) -> String {
+        if value.is_nan() {
+            return "NaN".to_string();
+        }
+        if value.is_infinite() {
+            return if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+        }
+        let magnitude = value.abs();
+        if magnitude == 0.0 || (1e-3..1e7).contains(&magnitude) {
+            return value.to_string();
+        }
+        // Rust 的 {:e} 输出 1e7、1.5e-4，尾数补上 ".0"，指数记号换成 E
+        let formatted = format!("{:e}", value);
+        let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+        if mantissa.contains('.') {
+            format!("{}E{}", mantissa, exponent)
+        } else {
+            format!("{}.0E{}", mantissa, exponent)
+        }
+    }
+    /// 值按 print 语句的格式转成字符串。展开很长的列表和映射时也检查时间和内存限制，超过时报错。
+    pub fn stringify(&mut self, obj: &Object) -> Result<String, RuntimeError> {
+        self.stringify_with(obj, Self::memory_usage)
+    }
+    /// 和 [`Interpreter::stringify`] 一样，由调用方统计当前的内存占用，见 [`Interpreter::allocate_with`]。
+    pub fn stringify_with(
+        &mut self,
+        obj: &Object,
+        memory_usage: impl Fn(&Self) -> usize,
+    ) -> Result<String, RuntimeError> {
+        // 嵌套很深的列表和映射也不能耗尽原生栈，所以用显式的栈代替递归，和 memory::Sizer 一样
+        let mut text = String::new();
+        // 已经计入分配的长度：共享元素的列表展开后可能比占用的内存大得多，边拼边计，超出上限就停下
+        let mut charged = 0;
+        let mut pending = vec![Piece::Value(obj.clone())];
+        // 正在打印的列表和映射，包含自身时打印成 [...] 或 {...}，避免无限循环
+        let mut open = HashSet::new();
+        let mut pieces: u64 = 0;
+        while let Some(piece) = pending.pop() {
+            // 互相引用的大列表展开后可能非常长，和执行语句一样要受时间限制
+            pieces += 1;
+            if pieces.is_multiple_of(TIME_CHECK_INTERVAL) {
+                self.check_deadline()?;
+            }
+            match piece {
+                Piece::Text(s) => text.push_str(s),
+                Piece::Close(ptr) => {
+                    open.remove(&ptr);
+                }
+                Piece::Value(Object::List(list)) => {
+                    let ptr = Rc::as_ptr(&list) as *const ();
+                    if !open.insert(ptr) {
+                        text.push_str("[...]");
+                        continue;
+                    }
+                    // 后进先出，所以倒着压栈
+                    text.push('[');
+                    pending.push(Piece::Close(ptr));
+                    pending.push(Piece::Text("]"));
+                    for (i, element) in list.borrow().iter().enumerate().rev() {
+                        pending.push(Piece::Value(element.get()));
+                        if i > 0 {
+                            pending.push(Piece::Text(", "));
+                        }
+                    }
+                }
+                Piece::Value(Object::Map(map)) => {
+                    let ptr = Rc::as_ptr(&map) as *const ();
+                    if !open.insert(ptr) {
+                        text.push_str("{...}");
+                        continue;
+                    }
+                    text.push('{');
+                    pending.push(Piece::Close(ptr));
+                    pending.push(Piece::Text("}"));
+                    for (i, (key, value)) in map.borrow().entries().iter().enumerate().rev() {
+                        pending.push(Piece::Value(value.get()));
+                        pending.push(Piece::Text(": "));
+                        pending.push(Piece::Value(key.object().clone()));
+                        if i > 0 {
+                            pending.push(Piece::Text(", "));
+                        }
+                    }
+                }
+                Piece::Value(value) => text.push_str(&self.stringify_scalar(&value)),
+            }
+            if text.len() - charged >= STRINGIFY_CHARGE_BYTES {
+                let bytes = text.len() - charged;
+                self.allocate_with(bytes, |interpreter| memory_usage(interpreter) + charged)?;
+                charged = text.len();
+            }
+        }
+        Ok(text)
+    }
+    // 不包含其它值的对象
+    fn stringify_scalar(&self, obj: &Object) -> String {
         match obj {
+            Object::Number(value) if self.compat => Self::java_number(*value),
             Object::Number(value) => value.to_string(),
-            Object::String(value) => value.clone(),
+            Object::String(value) => value.to_string(),
             Object::Boolean(value) => value.to_string(),
+            Object::NULL if self.compat => "nil".to_string(),
             Object::NULL => "null".to_string(),
             Object::Uninitialized => "uninitialized".to_string(),
+            Object::Function(function) => format!("<fn {}>", function.declaration.name.lexeme),
+            Object::Closure(closure) => format!("<fn {}>", closure.function.name),
+            Object::BoundMethod(bound) => format!("<fn {}>", bound.method.function.name),
+            Object::Callable(_) => "<native fn>".to_string(),
+            Object::Class(class) => class.name.clone(),
+            Object::Instance(instance) => format!("{} instance", instance.borrow().class.name),
+            Object::List(_) | Object::Map(_) => unreachable!("列表和映射由 stringify 展开"),
         }
     }
 }
+// stringify 待处理的片段：要打印的值、原样输出的文字，或者某个列表、映射打印完了
+enum Piece {
+    Value(Object),
+    Text(&'static str),
+    Close(*const ()),
+}
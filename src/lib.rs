@@ -0,0 +1,319 @@
+//! jlox 的 Rust 实现，可以作为库嵌入到其它程序中。
+//!
+//! 最简单的用法是通过 [`Lox`] 执行一段源码：
+//!
+//! ```
+//! let mut lox = jlox::Lox::new();
+//! lox.run_source("var a = 1;").unwrap();
+//! let value = lox.run_source("a + 2;").unwrap();
+//! assert!(matches!(value, jlox::Value::Number(n) if n == 3.0));
+//! ```
+//!
+//! 也可以直接使用 [`Scanner`]、[`Parser`]、[`Resolver`] 和 [`Interpreter`] 分步处理。
+use std::{fs, path::Path, rc::Rc};
+
+pub mod chunk;
+pub mod compiler;
+pub mod disassembler;
+pub mod environment;
+pub mod error;
+pub mod expr;
+pub mod gc;
+pub mod interpreter;
+pub mod line_editor;
+pub mod lox_callable;
+pub mod lox_class;
+pub mod lox_function;
+pub mod lox_instance;
+pub mod lox_map;
+pub mod loxc;
+pub mod memory;
+pub mod parser;
+pub mod repl;
+pub mod report;
+pub mod resolver;
+pub mod scanner;
+pub mod slot;
+pub mod symbol;
+pub mod token;
+pub mod token_type;
+pub mod vm;
+
+pub use chunk::{Chunk, Function, OpCode};
+pub use compiler::Compiler;
+pub use error::{Diagnostic, LoxError, Severity, Span};
+pub use expr::{Expr, Stmt};
+pub use gc::{GcConfig, GcStats};
+pub use interpreter::{Frame, Interpreter, RuntimeError, RuntimeErrorKind};
+pub use lox_callable::{LoxCallable, NativeFunction};
+pub use loxc::LoadError;
+pub use parser::Parser;
+pub use report::Sink;
+pub use resolver::Resolver;
+pub use scanner::Scanner;
+pub use symbol::Symbol;
+pub use token::{Object, Token};
+pub use vm::Vm;
+
+/// Lox 程序运行得到的值。
+pub type Value = Object;
+
+/// 执行 Lox 程序的方式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// 直接遍历语法树。每层 Lox 函数调用都会占用一段原生栈，默认的调用深度上限在普通的 8 MB
+    /// 栈上够用；调大 [`interpreter::Limits::max_call_depth`] 时要在更大的栈上运行（见 [`STACK_SIZE`]）
+    #[default]
+    TreeWalker,
+    /// 先编译成字节码，再交给栈式虚拟机执行
+    Vm,
+}
+
+/// 命令行运行树遍历解释器用的线程栈大小，留足余量给函数体嵌套很深的程序和调大了调用深度上限的
+/// 嵌入方。未优化的构建每层调用要约 10 KB 原生栈；操作系统只为实际用到的部分分配内存。
+pub const STACK_SIZE: usize = 1 << 30;
+
+/// 一个 Lox 解释器实例。多次调用 [`Lox::run_source`] 共享同一个全局环境。
+pub struct Lox {
+    interpreter: Interpreter,
+    vm: Vm,
+    backend: Backend,
+    output: Sink,
+    diagnostics: Sink,
+    compat: bool,
+    dump_bytecode: bool,
+}
+impl Lox {
+    /// 程序输出写到标准输出，诊断信息写到标准错误输出。
+    pub fn new() -> Self {
+        Self::new_with_sinks(report::stdout(), report::stderr())
+    }
+    /// 指定程序输出（print 语句）和诊断信息（各阶段的错误）的去向。
+    ///
+    /// ```
+    /// use std::{cell::RefCell, rc::Rc};
+    ///
+    /// let output = Rc::new(RefCell::new(Vec::new()));
+    /// let diagnostics = Rc::new(RefCell::new(Vec::new()));
+    /// let mut lox = jlox::Lox::new_with_sinks(output.clone(), diagnostics.clone());
+    /// lox.run_source("print 1 + 2;").unwrap();
+    /// let errors = lox.run_source("print ;").unwrap_err();
+    /// assert!(matches!(&errors[0], jlox::LoxError::Syntax(d) if d.span.line == 1));
+    /// assert_eq!(output.borrow().as_slice(), b"3\n");
+    /// assert!(String::from_utf8_lossy(&diagnostics.borrow()).contains("Expect expression."));
+    /// ```
+    pub fn new_with_sinks(output: Sink, diagnostics: Sink) -> Self {
+        Lox {
+            interpreter: Interpreter::new_with_output(output.clone()),
+            vm: Vm::new(),
+            backend: Backend::default(),
+            output,
+            diagnostics,
+            compat: false,
+            dump_bytecode: false,
+        }
+    }
+    /// 兼容模式：语法、输出格式和错误信息与书中的 jlox 完全一致，用来跑官方测试集。
+    /// 这个实现扩展的语法（列表、映射、标签、break/continue/in、转义和插值）都被关掉。
+    ///
+    /// ```
+    /// use std::{cell::RefCell, rc::Rc};
+    ///
+    /// let output = Rc::new(RefCell::new(Vec::new()));
+    /// let diagnostics = Rc::new(RefCell::new(Vec::new()));
+    /// let mut lox = jlox::Lox::new_with_sinks(output.clone(), diagnostics.clone());
+    /// lox.set_compat(true);
+    /// lox.run_source("var a; print a;").unwrap();
+    /// assert!(lox.run_source("print 1 +;").is_err());
+    /// lox.run_source("var break = \"a\\n\"; print break;").unwrap();
+    /// assert_eq!(output.borrow().as_slice(), b"nil\na\\n\n");
+    /// assert_eq!(
+    ///     String::from_utf8_lossy(&diagnostics.borrow()),
+    ///     "[line 1] Error at ';': Expect expression.\n"
+    /// );
+    /// ```
+    pub fn set_compat(&mut self, compat: bool) {
+        self.compat = compat;
+        self.interpreter.set_compat(compat);
+    }
+    /// 选择执行方式。两种方式的输出、错误信息和调用栈相同，共享同一个全局环境。
+    ///
+    /// ```
+    /// let mut lox = jlox::Lox::new();
+    /// lox.set_backend(jlox::Backend::Vm);
+    /// lox.run_source("fun add(a, b) { return a + b; }").unwrap();
+    /// let value = lox.run_source("add(1, 2);").unwrap();
+    /// assert!(matches!(value, jlox::Value::Number(n) if n == 3.0));
+    /// ```
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+    /// 只编译不执行：把源码编译出的字节码反汇编后写到程序输出（见 [`disassembler::disassemble`]），
+    /// 用来检查编译结果。这时 [`Lox::run_source`] 等方法总是返回 nil。
+    ///
+    /// ```
+    /// use std::{cell::RefCell, rc::Rc};
+    ///
+    /// let output = Rc::new(RefCell::new(Vec::new()));
+    /// let mut lox = jlox::Lox::new_with_sinks(output.clone(), output.clone());
+    /// lox.set_dump_bytecode(true);
+    /// lox.run_source("var a = 1;").unwrap();
+    /// let text = String::from_utf8(output.borrow().clone()).unwrap();
+    /// assert!(text.starts_with("== <script> ==\n0000    1 OP_STEP_AT\n"));
+    /// ```
+    pub fn set_dump_bytecode(&mut self, dump: bool) {
+        self.dump_bytecode = dump;
+    }
+    /// 执行一段源码，返回最后一条顶层语句的值（只有表达式语句有值，其余为 nil）。
+    ///
+    /// 出错时返回出错阶段的所有错误，同时把它们打印到诊断输出。
+    pub fn run_source(&mut self, source: &str) -> Result<Value, Vec<LoxError>> {
+        self.run(None, source, false, false)
+    }
+    /// 和 [`Lox::run_source`] 一样，但错误里会带上源码所在的文件名。
+    pub fn run_named(&mut self, file: &str, source: &str) -> Result<Value, Vec<LoxError>> {
+        self.run(Some(file), source, false, false)
+    }
+    /// 和 [`Lox::run_named`] 一样，file 是源码文件的路径。用字节码虚拟机执行时，编译结果缓存在
+    /// 源码旁边的 `.loxc` 文件里（见 [`loxc`]）：源码没有改过就直接读取缓存，跳过词法分析、
+    /// 语法分析和编译。缓存损坏或者格式版本不同时在诊断输出里提示一句，然后重新编译并覆盖它。
+    /// 源码文件本身以 `.loxc` 结尾时不使用缓存。
+    pub fn run_cached(&mut self, file: &str, source: &str) -> Result<Value, Vec<LoxError>> {
+        self.run(Some(file), source, false, true)
+    }
+    /// 交互式执行：最后一条语句是表达式语句时，把它的值打印到程序输出，就像 print 一样。
+    pub fn run_interactive(&mut self, source: &str) -> Result<Value, Vec<LoxError>> {
+        self.run(None, source, true, false)
+    }
+    fn run(
+        &mut self,
+        file: Option<&str>,
+        source: &str,
+        echo: bool,
+        cache: bool,
+    ) -> Result<Value, Vec<LoxError>> {
+        self.interpreter.set_file(file);
+        let cache = match file {
+            // 兼容模式下同一份源码的编译结果不同，不读写缓存
+            Some(file)
+                if cache && self.backend == Backend::Vm && !self.dump_bytecode && !self.compat =>
+            {
+                // 脚本自己的扩展名就是 .loxc 时缓存路径就是源码，不能拿编译结果覆盖它
+                Some(loxc::cache_path(file)).filter(|path| path != Path::new(file))
+            }
+            _ => None,
+        };
+        let result = match cache
+            .as_deref()
+            .and_then(|path| self.load_cache(path, source))
+        {
+            Some(script) => self.run_vm(script),
+            None => self.execute(source, echo, cache.as_deref()),
+        };
+        result.map_err(|mut errors| {
+            let source_lines: Vec<String> = source.lines().map(String::from).collect();
+            for error in &mut errors {
+                let diagnostic = error.diagnostic_mut();
+                // 运行时错误可能发生在之前执行的另一个文件定义的函数里，以最内层帧的文件为准
+                diagnostic.file = match diagnostic.trace.iter().rev().find(|f| f.line != 0) {
+                    Some(frame) => frame.file.as_deref().map(String::from),
+                    None => file.map(String::from),
+                };
+                // 不是这次执行的文件就没有对应的源码可以展示
+                let lines: &[String] = if diagnostic.file.as_deref() == file {
+                    &source_lines
+                } else {
+                    &[]
+                };
+                if self.compat {
+                    report::render_compat(&self.diagnostics, error);
+                } else {
+                    report::render(&self.diagnostics, error, lines);
+                }
+            }
+            errors
+        })
+    }
+    // 读取源码对应的缓存；没有缓存或者源码改过时返回 None，重新编译后会覆盖它
+    fn load_cache(&self, path: &Path, source: &str) -> Option<Rc<Function>> {
+        let bytes = fs::read(path).ok()?;
+        match loxc::decode(&bytes, loxc::source_hash(source)) {
+            Ok(script) => Some(script),
+            Err(LoadError::Stale) => None,
+            Err(e) => {
+                let message = format!("Ignoring cache file '{}': {}", path.display(), e);
+                let warning = Diagnostic::warning(Span::point(0, 0), message);
+                report::render_warning(&self.diagnostics, &warning, &[]);
+                None
+            }
+        }
+    }
+    fn run_vm(&mut self, script: Rc<Function>) -> Result<Value, Vec<LoxError>> {
+        self.vm
+            .run(&mut self.interpreter, script)
+            .map_err(|e| vec![LoxError::from(e)])
+    }
+    fn execute(
+        &mut self,
+        source: &str,
+        echo: bool,
+        cache: Option<&Path>,
+    ) -> Result<Value, Vec<LoxError>> {
+        let (tokens, mut errors) =
+            Scanner::new_with_compat(self.compat).scan_all(source.to_string());
+        // 词法错误之后照样做语法分析，一次报告尽量多的错误
+        let parsed = Parser::new_with_compat(tokens, self.compat).parse();
+        let mut statements = match parsed {
+            Ok(statements) if errors.is_empty() => statements,
+            Ok(_) => return Err(errors),
+            Err(parse_errors) => {
+                errors.extend(parse_errors);
+                return Err(errors);
+            }
+        };
+        // 把最后的表达式语句换成 print 语句
+        if echo
+            && let Some(Stmt::Expression(expr)) =
+                statements.pop_if(|stmt| matches!(stmt, Stmt::Expression(_)))
+        {
+            statements.push(Stmt::Print {
+                keyword: None,
+                expr,
+            });
+        }
+        Resolver::new().resolve(&statements)?;
+        if self.dump_bytecode {
+            let script = Compiler::new().compile(&statements)?;
+            let text = disassembler::disassemble(&script);
+            return match write!(self.output.borrow_mut(), "{}", text) {
+                Ok(()) => Ok(Value::NULL),
+                Err(e) => {
+                    let error = RuntimeError::new(format!("Failed to write output: {}", e));
+                    Err(vec![LoxError::from(error)])
+                }
+            };
+        }
+        match self.backend {
+            Backend::TreeWalker => self
+                .interpreter
+                .interpret(statements)
+                .map_err(|e| vec![LoxError::from(e)]),
+            Backend::Vm => {
+                let script = Compiler::new().compile(&statements)?;
+                if let Some(path) = cache {
+                    // 缓存只是为了加快下次启动，写不了（例如目录只读）不影响执行
+                    let _ = fs::write(path, loxc::encode(&script, loxc::source_hash(source)));
+                }
+                self.run_vm(script)
+            }
+        }
+    }
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+}
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
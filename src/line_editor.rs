@@ -0,0 +1,334 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, IsTerminal, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+// 历史文件最多保留的条数
+const MAX_HISTORY: usize = 1000;
+
+/// REPL 用的行编辑器，支持光标移动、删除和上下翻历史，历史保存在文件里。
+///
+/// 标准输入不是终端（例如管道）时退化为普通的按行读取，不打印提示符。
+pub struct LineEditor {
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+/// 读一行的结果。
+pub enum ReadLine {
+    Line(String),
+    /// Ctrl-C：放弃当前输入
+    Interrupted,
+    /// Ctrl-D 或输入结束
+    Eof,
+}
+
+impl LineEditor {
+    /// 不读写历史文件。
+    pub fn new() -> Self {
+        LineEditor {
+            history: Vec::new(),
+            history_path: None,
+        }
+    }
+    /// 从 path 加载历史，之后输入的每一行都追加到这个文件。
+    pub fn new_with_history(path: PathBuf) -> Self {
+        let mut history: Vec<String> = fs::read_to_string(&path)
+            .map(|content| content.lines().map(String::from).collect())
+            .unwrap_or_default();
+        if history.len() > MAX_HISTORY {
+            history.drain(..history.len() - MAX_HISTORY);
+            // 顺便把文件截短，写失败也不影响使用
+            let _ = fs::write(&path, history.join("\n") + "\n");
+        }
+        LineEditor {
+            history,
+            history_path: Some(path),
+        }
+    }
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if let Some(path) = &self.history_path
+            && let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path)
+        {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        // 输入来自管道时没有人看提示符，不打印，免得混进程序输出
+        if !io::stdin().is_terminal() {
+            return Self::read_plain_line("");
+        }
+        match RawMode::enable() {
+            Some(_raw) => self.edit_line(prompt),
+            // stty 不可用时同样退化为普通读取
+            None => Self::read_plain_line(prompt),
+        }
+    }
+
+    fn read_plain_line(prompt: &str) -> io::Result<ReadLine> {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(ReadLine::Eof);
+        }
+        let len = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(len);
+        Ok(ReadLine::Line(line))
+    }
+
+    // 原始模式下逐个按键处理，每次修改后重画整行
+    fn edit_line(&mut self, prompt: &str) -> io::Result<ReadLine> {
+        let mut buffer: Vec<char> = Vec::new();
+        // 光标位置，以字符计
+        let mut cursor = 0;
+        // 正在查看的历史条目；等于 history.len() 时表示正在编辑的新行
+        let mut history_index = self.history.len();
+        // 翻历史之前正在编辑的内容
+        let mut draft: Vec<char> = Vec::new();
+        let mut stdin = io::stdin().lock();
+        let mut stdout = io::stdout().lock();
+        Self::refresh(&mut stdout, prompt, &buffer, cursor)?;
+        loop {
+            let Some(key) = Self::read_key(&mut stdin)? else {
+                return Ok(ReadLine::Eof);
+            };
+            match key {
+                Key::Enter => {
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(ReadLine::Line(buffer.into_iter().collect()));
+                }
+                Key::Interrupt => {
+                    write!(stdout, "^C\r\n")?;
+                    stdout.flush()?;
+                    return Ok(ReadLine::Interrupted);
+                }
+                Key::Eof if buffer.is_empty() => {
+                    write!(stdout, "\r\n")?;
+                    stdout.flush()?;
+                    return Ok(ReadLine::Eof);
+                }
+                // 行不为空时 Ctrl-D 删除光标处的字符
+                Key::Eof | Key::Delete => {
+                    if cursor < buffer.len() {
+                        buffer.remove(cursor);
+                    }
+                }
+                Key::Char(c) => {
+                    buffer.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Backspace => {
+                    if cursor > 0 {
+                        cursor -= 1;
+                        buffer.remove(cursor);
+                    }
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(buffer.len()),
+                Key::Home => cursor = 0,
+                Key::End => cursor = buffer.len(),
+                Key::KillToStart => {
+                    buffer.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::KillToEnd => buffer.truncate(cursor),
+                Key::Up => {
+                    if history_index > 0 {
+                        if history_index == self.history.len() {
+                            draft = buffer.clone();
+                        }
+                        history_index -= 1;
+                        buffer = self.history[history_index].chars().collect();
+                        cursor = buffer.len();
+                    }
+                }
+                Key::Down => {
+                    if history_index < self.history.len() {
+                        history_index += 1;
+                        buffer = if history_index == self.history.len() {
+                            std::mem::take(&mut draft)
+                        } else {
+                            self.history[history_index].chars().collect()
+                        };
+                        cursor = buffer.len();
+                    }
+                }
+                Key::Ignored => {}
+            }
+            Self::refresh(&mut stdout, prompt, &buffer, cursor)?;
+        }
+    }
+
+    // 回到行首重画提示符和内容，清掉行尾残留，再把光标移到正确的位置
+    fn refresh(
+        out: &mut impl Write,
+        prompt: &str,
+        buffer: &[char],
+        cursor: usize,
+    ) -> io::Result<()> {
+        let line: String = buffer.iter().collect();
+        write!(out, "\r{}{}\x1b[K", prompt, line)?;
+        let back = buffer.len() - cursor;
+        if back > 0 {
+            write!(out, "\x1b[{}D", back)?;
+        }
+        out.flush()
+    }
+
+    fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+        let Some(byte) = Self::read_byte(input)? else {
+            return Ok(None);
+        };
+        let key = match byte {
+            b'\r' | b'\n' => Key::Enter,
+            0x01 => Key::Home,      // Ctrl-A
+            0x02 => Key::Left,      // Ctrl-B
+            0x03 => Key::Interrupt, // Ctrl-C
+            0x04 => Key::Eof,       // Ctrl-D
+            0x05 => Key::End,       // Ctrl-E
+            0x06 => Key::Right,     // Ctrl-F
+            0x08 | 0x7f => Key::Backspace,
+            0x0b => Key::KillToEnd,   // Ctrl-K
+            0x0e => Key::Down,        // Ctrl-N
+            0x10 => Key::Up,          // Ctrl-P
+            0x15 => Key::KillToStart, // Ctrl-U
+            0x1b => Self::read_escape(input)?,
+            byte if byte < 0x20 => Key::Ignored,
+            byte => Self::read_utf8(input, byte)?,
+        };
+        Ok(Some(key))
+    }
+
+    // 方向键等特殊键以 ESC [ 或 ESC O 开头
+    fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+        let Some(kind) = Self::read_byte(input)? else {
+            return Ok(Key::Ignored);
+        };
+        if kind != b'[' && kind != b'O' {
+            return Ok(Key::Ignored);
+        }
+        let mut params = Vec::new();
+        loop {
+            let Some(byte) = Self::read_byte(input)? else {
+                return Ok(Key::Ignored);
+            };
+            // 参数部分是数字和分号，遇到其它字节序列结束
+            if byte.is_ascii_digit() || byte == b';' {
+                params.push(byte);
+                continue;
+            }
+            return Ok(match (byte, params.as_slice()) {
+                (b'A', _) => Key::Up,
+                (b'B', _) => Key::Down,
+                (b'C', _) => Key::Right,
+                (b'D', _) => Key::Left,
+                (b'H', _) => Key::Home,
+                (b'F', _) => Key::End,
+                (b'~', b"1" | b"7") => Key::Home,
+                (b'~', b"4" | b"8") => Key::End,
+                (b'~', b"3") => Key::Delete,
+                _ => Key::Ignored,
+            });
+        }
+    }
+
+    // 按首字节判断 UTF-8 编码的长度，把剩下的字节读完
+    fn read_utf8(input: &mut impl Read, first: u8) -> io::Result<Key> {
+        let len = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            match Self::read_byte(input)? {
+                Some(byte) => bytes.push(byte),
+                None => break,
+            }
+        }
+        Ok(match std::str::from_utf8(&bytes) {
+            Ok(s) => s.chars().next().map_or(Key::Ignored, Key::Char),
+            Err(_) => Key::Ignored,
+        })
+    }
+
+    fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        loop {
+            match input.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    KillToStart,
+    KillToEnd,
+    Interrupt,
+    Eof,
+    Ignored,
+}
+
+// 通过 stty 把终端切到原始模式（不回显、按键立即可读），离开作用域时恢复原来的设置
+struct RawMode {
+    saved: String,
+}
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        let output = Command::new("stty")
+            .arg("-g")
+            .stdin(Stdio::inherit())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let saved = String::from_utf8(output.stdout).ok()?.trim().to_string();
+        let status = Command::new("stty")
+            .args(["raw", "-echo"])
+            .stdin(Stdio::inherit())
+            .status()
+            .ok()?;
+        status.success().then_some(RawMode { saved })
+    }
+}
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = Command::new("stty")
+            .arg(&self.saved)
+            .stdin(Stdio::inherit())
+            .status();
+    }
+}
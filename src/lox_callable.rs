@@ -0,0 +1,58 @@
+use std::fmt;
+
+use crate::{
+    interpreter::{Interpreter, RuntimeError},
+    token::Object,
+};
+
+/// 可以被 Lox 代码调用的值：用户定义的函数、宿主程序注册的原生函数等。
+pub trait LoxCallable {
+    fn name(&self) -> &str;
+    fn arity(&self) -> usize;
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Object>,
+    ) -> Result<Object, RuntimeError>;
+}
+impl fmt::Debug for dyn LoxCallable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name())
+    }
+}
+
+type NativeFn = dyn Fn(&[Object]) -> Result<Object, RuntimeError>;
+
+/// 用 Rust 闭包实现的原生函数，通过 [`Interpreter::define_native`] 注册。
+pub struct NativeFunction {
+    name: String,
+    arity: usize,
+    function: Box<NativeFn>,
+}
+impl NativeFunction {
+    pub fn new<F>(name: &str, arity: usize, function: F) -> Self
+    where
+        F: Fn(&[Object]) -> Result<Object, RuntimeError> + 'static,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+}
+impl LoxCallable for NativeFunction {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn arity(&self) -> usize {
+        self.arity
+    }
+    fn call(
+        &self,
+        _interpreter: &mut Interpreter,
+        arguments: Vec<Object>,
+    ) -> Result<Object, RuntimeError> {
+        (self.function)(&arguments)
+    }
+}
@@ -0,0 +1,74 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    interpreter::{Interpreter, RuntimeError},
+    lox_callable::LoxCallable,
+    lox_instance::LoxInstance,
+    memory::{self, Garbage},
+    symbol::{Symbol, SymbolMap},
+    token::Object,
+};
+
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    // 树遍历解释器里是 Object::Function，字节码虚拟机里是 Object::Closure
+    pub methods: SymbolMap<Object>,
+}
+impl Drop for LoxClass {
+    // 父类和方法交给 memory::release，很长的继承链不会递归释放
+    fn drop(&mut self) {
+        let superclass = self.superclass.take().map(Object::Class);
+        let methods = self.methods.drain().map(|(_, method)| method);
+        memory::release(superclass.into_iter().chain(methods).map(Garbage::Object));
+    }
+}
+impl LoxClass {
+    pub fn new(name: String, superclass: Option<Rc<LoxClass>>, methods: SymbolMap<Object>) -> Self {
+        LoxClass {
+            name,
+            superclass,
+            methods,
+        }
+    }
+    // 先找自己的方法，找不到再沿着父类链向上找
+    pub fn find_method(&self, name: &Symbol) -> Option<Object> {
+        match self.methods.get(name) {
+            Some(method) => Some(method.clone()),
+            None => match &self.superclass {
+                Some(superclass) => superclass.find_method(name),
+                None => None,
+            },
+        }
+    }
+    // 调用类就是构造实例，参数个数由 init 决定
+    pub fn arity(&self) -> usize {
+        match self.find_method(&Symbol::intern("init")) {
+            Some(Object::Function(initializer)) => initializer.arity(),
+            Some(Object::Closure(initializer)) => initializer.function.arity,
+            _ => 0,
+        }
+    }
+    pub fn call(
+        self: &Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Object>,
+    ) -> Result<Object, RuntimeError> {
+        interpreter.allocate(size_of::<LoxInstance>())?;
+        let instance = Rc::new(RefCell::new(LoxInstance::new(self.clone())));
+        interpreter
+            .heap()
+            .track(&Object::Instance(instance.clone()));
+        if let Some(Object::Function(initializer)) = self.find_method(&Symbol::intern("init")) {
+            initializer
+                .bind(instance.clone())
+                .call(interpreter, arguments)?;
+        }
+        Ok(Object::Instance(instance))
+    }
+}
+impl fmt::Debug for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
@@ -0,0 +1,87 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    environment::Environment,
+    expr::FunctionDecl,
+    interpreter::{Interpreter, RuntimeError, Unwind},
+    lox_callable::LoxCallable,
+    lox_instance::LoxInstance,
+    symbol::Symbol,
+    token::Object,
+};
+
+pub struct LoxFunction {
+    pub declaration: Rc<FunctionDecl>,
+    // 声明函数时的环境，调用结束后依然存活
+    pub closure: Rc<RefCell<Environment>>,
+    // 类的 init 方法：无论怎样返回，结果都是 this
+    pub is_initializer: bool,
+    // 定义函数的源码所在的文件，用于调用栈
+    pub file: Option<Rc<str>>,
+}
+impl LoxFunction {
+    pub fn new(
+        declaration: Rc<FunctionDecl>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+        file: Option<Rc<str>>,
+    ) -> Self {
+        LoxFunction {
+            declaration,
+            closure,
+            is_initializer,
+            file,
+        }
+    }
+    // 生成绑定了 this 的新函数：在闭包外再包一层只含 this 的环境
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> LoxFunction {
+        let mut environment = Environment::new_with_enclosing(self.closure.clone());
+        environment.define(Symbol::intern("this"), Object::Instance(instance));
+        LoxFunction::new(
+            self.declaration.clone(),
+            Rc::new(RefCell::new(environment)),
+            self.is_initializer,
+            self.file.clone(),
+        )
+    }
+    fn this(&self) -> Object {
+        match self.closure.borrow().values.get(&Symbol::intern("this")) {
+            Some(this) => this.get(),
+            None => Object::NULL,
+        }
+    }
+}
+impl LoxCallable for LoxFunction {
+    fn name(&self) -> &str {
+        &self.declaration.name.lexeme
+    }
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Object>,
+    ) -> Result<Object, RuntimeError> {
+        // 每次调用都有自己的环境，外层是闭包环境，形参绑定在这里
+        let mut environment = Environment::new_with_enclosing(self.closure.clone());
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            environment.define(param.lexeme.clone(), argument);
+        }
+        match interpreter.execute_block(&self.declaration.body, environment) {
+            Ok(_) if self.is_initializer => Ok(self.this()),
+            Ok(_) => Ok(Object::NULL),
+            // return 语句通过 Unwind::Return 一路传到这里
+            Err(Unwind::Return(_)) if self.is_initializer => Ok(self.this()),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(e)) => Err(e),
+            // 语法分析保证 break/continue 不会跨越函数边界
+            Err(Unwind::Break(_)) | Err(Unwind::Continue(_)) => unreachable!(),
+        }
+    }
+}
+impl fmt::Debug for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.lexeme)
+    }
+}
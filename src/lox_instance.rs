@@ -0,0 +1,50 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    interpreter::{RuntimeError, RuntimeErrorKind},
+    lox_class::LoxClass,
+    slot::Slot,
+    symbol::SymbolMap,
+    token::{Object, Token},
+};
+
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    // 字段的槽位释放时自己走 memory::release，实例串成的长链表不会递归释放
+    pub fields: SymbolMap<Slot>,
+}
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        LoxInstance {
+            class,
+            fields: SymbolMap::default(),
+        }
+    }
+    // 字段优先于方法；取到的方法要绑定 this，所以需要实例本身的 Rc
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Object, RuntimeError> {
+        if let Some(value) = instance.borrow().fields.get(&name.lexeme) {
+            return Ok(value.get());
+        }
+        let method = instance.borrow().class.find_method(&name.lexeme);
+        match method {
+            Some(Object::Function(method)) => {
+                Ok(Object::Function(Rc::new(method.bind(instance.clone()))))
+            }
+            _ => Err(RuntimeError {
+                message: format!("Undefined property '{}'.", name.lexeme),
+                line: name.line,
+                column: name.column,
+                kind: RuntimeErrorKind::Error,
+                trace: Vec::new(),
+            }),
+        }
+    }
+    pub fn set(&mut self, name: &Token, value: Object) {
+        self.fields.insert(name.lexeme.clone(), Slot::new(value));
+    }
+}
+impl fmt::Debug for LoxInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
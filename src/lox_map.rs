@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use crate::{
+    interpreter::Interpreter,
+    memory::{self, Garbage},
+    slot::Slot,
+    token::Object,
+};
+
+// 可以作为映射键的值。相等性沿用 Interpreter::is_equal 的规则：
+// 数字、字符串、布尔值和 nil 按值比较，其它对象按引用比较
+#[derive(Debug, Clone)]
+pub struct MapKey(Object);
+impl MapKey {
+    pub fn new(key: Object) -> Result<MapKey, String> {
+        match key {
+            // NaN 不等于任何值（包括它自己），作为键永远取不出来，直接拒绝
+            Object::Number(n) if n.is_nan() => Err("NaN can't be used as a map key.".to_string()),
+            // 0.0 == -0.0，两者必须落到同一个键上（浮点字面量模式按 == 匹配，-0.0 也会进这里）
+            Object::Number(0.0) => Ok(MapKey(Object::Number(0.0))),
+            Object::Uninitialized => {
+                Err("Uninitialized value can't be used as a map key.".to_string())
+            }
+            key => Ok(MapKey(key)),
+        }
+    }
+    pub fn object(&self) -> &Object {
+        &self.0
+    }
+}
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        Interpreter::is_equal(&self.0, &other.0)
+    }
+}
+impl Eq for MapKey {}
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(&self.0).hash(state);
+        match &self.0 {
+            Object::String(s) => s.hash(state),
+            Object::Number(n) => n.to_bits().hash(state),
+            Object::Boolean(b) => b.hash(state),
+            Object::NULL | Object::Uninitialized => {}
+            Object::Function(f) => Rc::as_ptr(f).hash(state),
+            Object::Callable(c) => (Rc::as_ptr(c) as *const ()).hash(state),
+            Object::Class(c) => Rc::as_ptr(c).hash(state),
+            Object::Instance(i) => Rc::as_ptr(i).hash(state),
+            Object::List(l) => Rc::as_ptr(l).hash(state),
+            Object::Map(m) => Rc::as_ptr(m).hash(state),
+            Object::Closure(c) => Rc::as_ptr(c).hash(state),
+            Object::BoundMethod(b) => Rc::as_ptr(b).hash(state),
+        }
+    }
+}
+
+// 保持插入顺序的映射，打印和遍历键时顺序稳定
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(MapKey, Slot)>,
+    index: HashMap<MapKey, usize>,
+}
+impl Drop for LoxMap {
+    // 键交给 memory::release，值的槽位自己释放；先清空索引，条目里的键才是最后一份引用
+    fn drop(&mut self) {
+        self.index.clear();
+        memory::release(
+            self.entries
+                .drain(..)
+                .map(|(key, _)| Garbage::Object(key.0)),
+        );
+    }
+}
+impl LoxMap {
+    pub fn new() -> Self {
+        LoxMap::default()
+    }
+    pub fn get(&self, key: &MapKey) -> Option<Object> {
+        self.index.get(key).map(|&i| self.entries[i].1.get())
+    }
+    pub fn set(&mut self, key: MapKey, value: Object) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = Slot::new(value),
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, Slot::new(value)));
+            }
+        }
+    }
+    pub fn contains(&self, key: &MapKey) -> bool {
+        self.index.contains_key(key)
+    }
+    pub fn keys(&self) -> Vec<Slot> {
+        self.entries
+            .iter()
+            .map(|(k, _)| Slot::new(k.object().clone()))
+            .collect()
+    }
+    pub fn entries(&self) -> &[(MapKey, Slot)] {
+        &self.entries
+    }
+}
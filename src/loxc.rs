@@ -0,0 +1,495 @@
+//! `.loxc` 文件：编译好的程序的二进制格式，用来缓存编译结果。
+//!
+//! 所有整数都是小端。文件头之后是顶层脚本函数，函数常量里递归地存着里面定义的函数：
+//!
+//! ```text
+//! 文件头   "LOXC"  版本 u16  源码哈希 u64  正文校验和 u64
+//! 函数     名字  参数个数 u32  上值个数 u32  是否是 init u8
+//!          指令（长度 u32 + 字节）
+//!          常量池（个数 u32，每个常量一个字节的类型：0 数字 f64，1 字符串，2 函数）
+//!          行号表（个数 u32，每项 偏移、行、列 各 u32）
+//!          块表（个数 u32，每项 开始、结束、行、列 各 u32）
+//! 字符串   长度 u32 + UTF-8 字节
+//! ```
+use std::{fmt, path::Path, path::PathBuf, rc::Rc};
+
+use crate::{
+    chunk::{Block, Chunk, Constant, Function, Line, OpCode},
+    symbol::Symbol,
+};
+
+const MAGIC: &[u8; 4] = b"LOXC";
+/// 格式的版本。指令集或者文件布局有任何改动都要加一，旧文件会被当作不兼容的版本。
+pub const FORMAT_VERSION: u16 = 1;
+// 魔数、版本、源码哈希、校验和
+const HEADER_LEN: usize = 4 + 2 + 8 + 8;
+// 函数嵌套的层数上限，防止损坏的文件让递归读取耗尽栈
+const MAX_NESTING: usize = 256;
+
+/// 读取 `.loxc` 文件失败的原因。
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+    /// 由另一个版本的格式写出
+    Version(u16),
+    /// 源码在写出缓存之后改过了
+    Stale,
+    /// 不是 `.loxc` 文件，或者内容不完整、被损坏
+    Corrupt(String),
+}
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Version(version) => write!(
+                f,
+                "Unsupported format version {} (expected {}).",
+                version, FORMAT_VERSION
+            ),
+            LoadError::Stale => write!(f, "Source has changed since it was compiled."),
+            LoadError::Corrupt(reason) => write!(f, "Corrupt file: {}.", reason),
+        }
+    }
+}
+
+/// 源码的哈希（64 位 FNV-1a），不依赖 Rust 版本，写进文件里用来判断缓存是否过期。
+pub fn source_hash(source: &str) -> u64 {
+    fnv1a(source.as_bytes())
+}
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// 源码文件对应的缓存文件：同一目录下，扩展名换成 `.loxc`。
+pub fn cache_path(source_path: impl AsRef<Path>) -> PathBuf {
+    source_path.as_ref().with_extension("loxc")
+}
+
+/// 把编译好的脚本写成 `.loxc` 格式。
+pub fn encode(script: &Function, source_hash: u64) -> Vec<u8> {
+    let mut body = Vec::new();
+    write_function(&mut body, script);
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&source_hash.to_le_bytes());
+    bytes.extend_from_slice(&fnv1a(&body).to_le_bytes());
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+/// 读取 [`encode`] 写出的内容。源码哈希和 source_hash 不同时返回 [`LoadError::Stale`]。
+///
+/// 校验和用来发现意外的损坏。此外还会逐条检查指令：操作数完整、常量下标和类型正确、
+/// 上值下标和跳转不越界，并且沿着所有执行路径检查栈上的操作是配平的，局部变量的槽位都在帧里。
+/// 这些检查保证虚拟机不会因为文件损坏而崩溃，但不保证程序的行为，不要执行来源不明的文件。
+///
+/// ```
+/// use jlox::{Compiler, Parser, Scanner, loxc};
+///
+/// let source = "fun f(a) { return a + 1; } print f(2);";
+/// let (tokens, _) = Scanner::new().scan_all(source.to_string());
+/// let statements = Parser::new(tokens).parse().unwrap();
+/// let script = Compiler::new().compile(&statements).unwrap();
+///
+/// let mut bytes = loxc::encode(&script, loxc::source_hash(source));
+/// let loaded = loxc::decode(&bytes, loxc::source_hash(source)).unwrap();
+/// assert_eq!(loaded.chunk.code, script.chunk.code);
+/// assert!(matches!(loxc::decode(&bytes, 0), Err(loxc::LoadError::Stale)));
+///
+/// bytes.truncate(bytes.len() - 1);
+/// assert!(matches!(
+///     loxc::decode(&bytes, loxc::source_hash(source)),
+///     Err(loxc::LoadError::Corrupt(_))
+/// ));
+/// ```
+pub fn decode(bytes: &[u8], source_hash: u64) -> Result<Rc<Function>, LoadError> {
+    if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+        return Err(corrupt("not a .loxc file"));
+    }
+    let mut reader = Reader { bytes, position: 4 };
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(LoadError::Version(version));
+    }
+    if reader.u64()? != source_hash {
+        return Err(LoadError::Stale);
+    }
+    let checksum = reader.u64()?;
+    if fnv1a(&bytes[HEADER_LEN..]) != checksum {
+        return Err(corrupt("checksum mismatch"));
+    }
+    let script = reader.function(0)?;
+    if script.arity != 0 || script.upvalue_count != 0 {
+        return Err(corrupt("invalid script"));
+    }
+    if reader.position != bytes.len() {
+        return Err(corrupt("unexpected data after the program"));
+    }
+    Ok(Rc::new(script))
+}
+
+fn corrupt(reason: &str) -> LoadError {
+    LoadError::Corrupt(reason.to_string())
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u32).to_le_bytes());
+}
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_u32(out, value.len());
+    out.extend_from_slice(value.as_bytes());
+}
+fn write_function(out: &mut Vec<u8>, function: &Function) {
+    write_string(out, &function.name);
+    write_u32(out, function.arity);
+    write_u32(out, function.upvalue_count);
+    out.push(function.is_initializer as u8);
+    let chunk = &function.chunk;
+    write_u32(out, chunk.code.len());
+    out.extend_from_slice(&chunk.code);
+    write_u32(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Constant::Number(n) => {
+                out.push(0);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Constant::String(s) => {
+                out.push(1);
+                write_string(out, s);
+            }
+            Constant::Function(function) => {
+                out.push(2);
+                write_function(out, function);
+            }
+        }
+    }
+    write_u32(out, chunk.lines.len());
+    for line in &chunk.lines {
+        write_u32(out, line.offset);
+        write_u32(out, line.line);
+        write_u32(out, line.column);
+    }
+    write_u32(out, chunk.blocks.len());
+    for block in &chunk.blocks {
+        write_u32(out, block.start);
+        write_u32(out, block.end);
+        write_u32(out, block.line);
+        write_u32(out, block.column);
+    }
+}
+
+// 按顺序读取文件内容，读过头时报告文件不完整
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| corrupt("unexpected end of file"))?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+    fn u64(&mut self) -> Result<u64, LoadError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+    fn string(&mut self) -> Result<String, LoadError> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt("invalid UTF-8 in a string"))
+    }
+    // 个数字段：每项至少占 item_size 个字节，先按剩余长度检查，免得按损坏的个数分配大量内存
+    fn count(&mut self, item_size: usize) -> Result<usize, LoadError> {
+        let count = self.u32()?;
+        if count.saturating_mul(item_size) > self.bytes.len() - self.position {
+            return Err(corrupt("unexpected end of file"));
+        }
+        Ok(count)
+    }
+    fn function(&mut self, depth: usize) -> Result<Function, LoadError> {
+        if depth > MAX_NESTING {
+            return Err(corrupt("functions nested too deeply"));
+        }
+        let mut function = Function::new(self.string()?);
+        function.arity = self.u32()?;
+        function.upvalue_count = self.u32()?;
+        function.is_initializer = match self.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(corrupt("invalid flag")),
+        };
+        let len = self.u32()?;
+        let mut chunk = Chunk::new();
+        chunk.code = self.take(len)?.to_vec();
+        for _ in 0..self.count(1)? {
+            let constant = match self.u8()? {
+                0 => {
+                    let bits = self.u64()?;
+                    Constant::Number(f64::from_bits(bits))
+                }
+                1 => Constant::String(Symbol::from(self.string()?)),
+                2 => Constant::Function(Rc::new(self.function(depth + 1)?)),
+                _ => return Err(corrupt("unknown constant type")),
+            };
+            chunk.constants.push(constant);
+        }
+        for _ in 0..self.count(12)? {
+            chunk.lines.push(Line {
+                offset: self.u32()?,
+                line: self.u32()?,
+                column: self.u32()?,
+            });
+        }
+        for _ in 0..self.count(16)? {
+            chunk.blocks.push(Block {
+                start: self.u32()?,
+                end: self.u32()?,
+                line: self.u32()?,
+                column: self.u32()?,
+            });
+        }
+        function.chunk = chunk;
+        verify(&function)?;
+        Ok(function)
+    }
+}
+
+// 逐条检查指令的格式：虚拟机信任编译器生成的字节码，不会再检查这些
+fn verify(function: &Function) -> Result<(), LoadError> {
+    let invalid = |offset: usize, what: &str| {
+        LoadError::Corrupt(format!(
+            "{} at offset {} in '{}'",
+            what, offset, function.name
+        ))
+    };
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+    let u16_at = |at: usize| -> Option<usize> {
+        let bytes = code.get(at..at + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+    };
+    let string_at = |at: usize| -> bool {
+        matches!(
+            u16_at(at).and_then(|i| chunk.constants.get(i)),
+            Some(Constant::String(_))
+        )
+    };
+    // 每条指令开始处记下下一条指令的位置，其余位置是 0
+    let mut next_at = vec![0; code.len()];
+    let mut offset = 0;
+    let mut last = None;
+    while offset < code.len() {
+        let op =
+            OpCode::from_byte(code[offset]).ok_or_else(|| invalid(offset, "unknown opcode"))?;
+        last = Some(op);
+        let next = match op {
+            OpCode::Constant => match u16_at(offset + 1).and_then(|i| chunk.constants.get(i)) {
+                Some(Constant::Number(_) | Constant::String(_)) => offset + 3,
+                _ => return Err(invalid(offset, "invalid constant")),
+            },
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::CheckInitialized
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::CheckInstance
+            | OpCode::GetSuper => {
+                if !string_at(offset + 1) {
+                    return Err(invalid(offset, "invalid name constant"));
+                }
+                offset + 3
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                if u16_at(offset + 1).is_none_or(|index| index >= function.upvalue_count) {
+                    return Err(invalid(offset, "invalid upvalue"));
+                }
+                offset + 3
+            }
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::List
+            | OpCode::Map
+            | OpCode::Interpolate => {
+                u16_at(offset + 1).ok_or_else(|| invalid(offset, "truncated instruction"))?;
+                offset + 3
+            }
+            OpCode::Call => {
+                code.get(offset + 1)
+                    .ok_or_else(|| invalid(offset, "truncated instruction"))?;
+                offset + 2
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let distance =
+                    u16_at(offset + 1).ok_or_else(|| invalid(offset, "truncated instruction"))?;
+                let target = match op {
+                    OpCode::Loop => (offset + 3).checked_sub(distance),
+                    _ => Some(offset + 3 + distance),
+                };
+                if target.is_none_or(|target| target >= code.len()) {
+                    return Err(invalid(offset, "jump out of range"));
+                }
+                offset + 3
+            }
+            OpCode::Closure => {
+                let inner = match u16_at(offset + 1).and_then(|i| chunk.constants.get(i)) {
+                    Some(Constant::Function(inner)) => inner,
+                    _ => return Err(invalid(offset, "invalid function constant")),
+                };
+                let end = offset + 3 + inner.upvalue_count * 3;
+                if end > code.len() {
+                    return Err(invalid(offset, "truncated instruction"));
+                }
+                // 捕获外层函数的上值时，下标要在外层函数的上值个数以内
+                for at in (offset + 3..end).step_by(3) {
+                    let index = u16_at(at + 1).unwrap_or(0);
+                    if code[at] > 1 || (code[at] == 0 && index >= function.upvalue_count) {
+                        return Err(invalid(offset, "invalid upvalue"));
+                    }
+                }
+                end
+            }
+            OpCode::Class => {
+                if !string_at(offset + 1) || offset + 6 > code.len() {
+                    return Err(invalid(offset, "invalid class instruction"));
+                }
+                offset + 6
+            }
+            _ => offset + 1,
+        };
+        next_at[offset] = next;
+        offset = next;
+    }
+    // 最后一条指令必须是返回，执行不会越过字节码的末尾
+    if last != Some(OpCode::Return) {
+        return Err(invalid(code.len(), "missing return"));
+    }
+    verify_stack(function, &next_at).map_err(|(offset, what)| invalid(offset, what))
+}
+
+// 沿着所有执行路径算出每条指令执行前帧里有几个值（包括槽位 0 的被调用者），检查跳转落在
+// 指令开头、弹出的值和访问的局部变量都在帧里、几条路径汇合时栈的高度相同。
+// 这样虚拟机执行时栈不会越界，槽位 0 也不会被弹出
+fn verify_stack(function: &Function, next_at: &[usize]) -> Result<(), (usize, &'static str)> {
+    let code = &function.chunk.code;
+    let operand = |offset: usize| function.chunk.read_u16(offset + 1) as usize;
+    let mut heights: Vec<Option<usize>> = vec![None; code.len()];
+    let mut pending = vec![(0, function.arity + 1)];
+    while let Some((offset, height)) = pending.pop() {
+        match heights.get(offset) {
+            Some(Some(known)) if *known == height => continue,
+            Some(Some(_)) => return Err((offset, "inconsistent stack height")),
+            Some(None) if next_at[offset] != 0 => heights[offset] = Some(height),
+            _ => return Err((offset, "jump into the middle of an instruction")),
+        }
+        let op = OpCode::from_byte(code[offset]).expect("第一遍已经检查过操作码");
+        let next = next_at[offset];
+        // 用到栈顶的几个值、弹出几个、压入几个
+        let (used, popped, pushed) = match op {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Uninitialized
+            | OpCode::GetGlobal
+            | OpCode::GetUpvalue => (0, 0, 1),
+            OpCode::GetLocal | OpCode::SetLocal => {
+                if operand(offset) >= height {
+                    return Err((offset, "invalid local slot"));
+                }
+                match op {
+                    OpCode::GetLocal => (0, 0, 1),
+                    _ => (1, 0, 0),
+                }
+            }
+            OpCode::Closure => {
+                for at in (offset + 3..next).step_by(3) {
+                    if code[at] == 1 && operand(at) >= height {
+                        return Err((offset, "invalid local slot"));
+                    }
+                }
+                (0, 0, 1)
+            }
+            OpCode::Pop | OpCode::DefineGlobal | OpCode::Print | OpCode::CloseUpvalue => (1, 1, 0),
+            OpCode::SetGlobal
+            | OpCode::SetUpvalue
+            | OpCode::CheckInitialized
+            | OpCode::CheckInstance
+            | OpCode::CheckSuperclass
+            | OpCode::CheckKey
+            | OpCode::JumpIfFalse => (1, 0, 0),
+            OpCode::GetProperty | OpCode::Not | OpCode::Negate | OpCode::Stringify => (1, 1, 1),
+            OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::In
+            | OpCode::GetIndex => (2, 2, 1),
+            OpCode::SetIndex => (3, 3, 1),
+            OpCode::Call => {
+                let count = code[offset + 1] as usize + 1;
+                (count, count, 1)
+            }
+            OpCode::List | OpCode::Interpolate => {
+                let count = operand(offset);
+                (count, count, 1)
+            }
+            // 方法个数在名字常量后面
+            OpCode::Class => {
+                let count = operand(offset + 2);
+                (count, count, 1)
+            }
+            OpCode::Map => {
+                let count = operand(offset) * 2;
+                (count, count, 1)
+            }
+            OpCode::Return => (1, 1, 0),
+            OpCode::Jump | OpCode::Loop | OpCode::Step | OpCode::StepAt => (0, 0, 0),
+        };
+        if used >= height {
+            return Err((offset, "stack underflow"));
+        }
+        let height = height - popped + pushed;
+        match op {
+            OpCode::Return => {}
+            OpCode::Jump => pending.push((next + operand(offset), height)),
+            OpCode::Loop => pending.push((next - operand(offset), height)),
+            OpCode::JumpIfFalse => {
+                pending.push((next + operand(offset), height));
+                pending.push((next, height));
+            }
+            _ => pending.push((next, height)),
+        }
+    }
+    Ok(())
+}
@@ -1,54 +1,88 @@
-use std::{env, fs};
+use std::{env, fs, process, thread};
 
-use interpreter::Interpreter;
-use parser::Parser;
-use scanner::Scanner;
-mod environment;
-mod expr;
-mod interpreter;
-mod parser;
-mod report;
-mod scanner;
-mod token;
-mod token_type;
+use jlox::{Backend, GcConfig, Lox, LoxError, STACK_SIZE, repl::Repl, report};
+
+// 退出码沿用 jlox（BSD sysexits）：用法错误 64，编译错误 65，运行时错误 70，读写错误 74
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    // 函数体嵌套很深时每层递归要用更多原生栈，换到一个栈留足余量的线程上执行
+    let child = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_main)
+        .unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(70);
+        });
+    if child.join().is_err() {
+        process::exit(70);
+    }
+}
+fn run_main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
     // let args: Vec<&'static str> = vec!["name", r"./test.lox"]; //这行是测试代码
-    match args.len() {
-        2 => {
-            run_file(args[1].to_string());
+    let mut lox = Lox::new();
+    // --compat：输出和退出码与书中的 jlox 一致，用来跑官方测试集
+    if take_flag(&mut args, "--compat") {
+        lox.set_compat(true);
+    }
+    // --vm：编译成字节码，用虚拟机执行
+    if take_flag(&mut args, "--vm") {
+        lox.set_backend(Backend::Vm);
+    }
+    // --dump-bytecode：只编译，打印反汇编出的字节码，不执行
+    if take_flag(&mut args, "--dump-bytecode") {
+        lox.set_dump_bytecode(true);
+    }
+    // --gc-stress：每次分配都做一次垃圾回收；--gc-log：每次回收后在标准错误输出报告回收了多少
+    let mut gc = GcConfig::default();
+    if take_flag(&mut args, "--gc-stress") {
+        gc.stress = true;
+    }
+    if take_flag(&mut args, "--gc-log") {
+        gc.log = Some(report::stderr());
+    }
+    lox.interpreter().set_gc(gc);
+    match args.as_slice() {
+        [] => {
+            if let Err(e) = Repl::new_with_lox(lox).run() {
+                eprintln!("{}", e);
+                process::exit(74);
+            }
         }
+        [path] => run_file(lox, path),
         _ => {
-            panic!("Usage: jlox-rust filename");
+            eprintln!(
+                "Usage: jlox-rust [--compat] [--vm] [--dump-bytecode] [--gc-stress] [--gc-log] [script]"
+            );
+            process::exit(64);
         }
     }
 }
-fn run_file(path: String) {
-    let f = fs::read_to_string(path).expect("lox文件读取失败");
-    run(f);
+// 参数里有这个开关就把它去掉，返回 true
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
 }
-fn run(source: String) {
-    let mut scanner = Scanner::new();
-    let tokens = scanner.scan_tokens(source.clone()); // Clone source for parser
-    match tokens {
-        Ok(tokens) => {
-            let mut parser = Parser::new(tokens, source); // Pass source to parser
-            match parser.parse() {
-                Ok(expr) => match Interpreter::new().interpret(expr) {
-                    Ok(_obj) => {}
-                    Err(e) => {
-                        eprintln!("Runtime error: {} at line {}", e.message, e.line)
-                    }
-                },
-                Err(_e) => {
-                    //语法分析出现错误，简单退出进程
-                    std::process::exit(65);
-                }
-            }
+fn run_file(lox: Lox, path: &str) {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Could not read file '{}': {}", path, e);
+            process::exit(74);
         }
-        Err(_e) => {
-            //词法分析出现错误，简单退出进程
-            std::process::exit(65);
+    };
+    run(lox, path, &source);
+}
+fn run(mut lox: Lox, path: &str, source: &str) {
+    // 错误已经由 Lox 打印到标准错误输出，这里只决定退出码
+    if let Err(errors) = lox.run_cached(path, source) {
+        match errors[0] {
+            LoxError::Runtime(_) | LoxError::Limit(..) => process::exit(70),
+            _ => process::exit(65),
         }
     }
 }
@@ -0,0 +1,249 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    mem::size_of,
+    rc::Rc,
+};
+
+use crate::{
+    environment::Environment,
+    lox_function::LoxFunction,
+    slot::Slot,
+    symbol::Symbol,
+    token::Object,
+    vm::{BoundMethod, Closure, Upvalue},
+};
+
+// 估算从 environments 和 objects 出发能访问到的所有值占用的字节数。
+// 只统计堆上的数据和存放值的槽位，不追求精确；共享的对象只算一次。
+// objects 是环境之外的根，例如字节码虚拟机栈上的值
+pub fn reachable_bytes<'a>(
+    environments: impl IntoIterator<Item = &'a Rc<RefCell<Environment>>>,
+    objects: impl IntoIterator<Item = &'a Slot>,
+) -> usize {
+    let mut sizer = Sizer::default();
+    for root in environments {
+        sizer.pending.push(Pending::Environment(root.clone()));
+    }
+    for object in objects {
+        sizer.bytes += size_of::<Slot>();
+        object.with(|object| sizer.visit(object));
+    }
+    sizer.run();
+    sizer.bytes
+}
+
+// 还没统计的环境或值；用显式的栈代替递归，很长的链表也不会爆栈
+enum Pending {
+    Environment(Rc<RefCell<Environment>>),
+    Function(Rc<LoxFunction>),
+    Object(Object),
+}
+
+#[derive(Default)]
+struct Sizer {
+    bytes: usize,
+    seen: HashSet<*const ()>,
+    pending: Vec<Pending>,
+}
+impl Sizer {
+    fn run(&mut self) {
+        while let Some(next) = self.pending.pop() {
+            match next {
+                Pending::Environment(environment) => self.environment(&environment),
+                Pending::Function(function) => self.function(&function),
+                Pending::Object(object) => self.object(&object),
+            }
+        }
+    }
+    // 第一次遇到这个对象时返回 true
+    fn first_visit<T: ?Sized>(&mut self, ptr: *const T) -> bool {
+        self.seen.insert(ptr as *const ())
+    }
+    fn environment(&mut self, environment: &Rc<RefCell<Environment>>) {
+        if !self.first_visit(Rc::as_ptr(environment)) {
+            return;
+        }
+        let environment = environment.borrow();
+        self.bytes += size_of::<Environment>();
+        for (name, value) in &environment.values {
+            self.bytes += size_of::<Slot>();
+            self.string(name);
+            value.with(|value| self.visit(value));
+        }
+        if let Some(enclosing) = &environment.enclosing {
+            self.pending.push(Pending::Environment(enclosing.clone()));
+        }
+    }
+    fn function(&mut self, function: &Rc<LoxFunction>) {
+        if !self.first_visit(Rc::as_ptr(function)) {
+            return;
+        }
+        // 语法树是源码的一部分，不算在运行时内存里
+        self.bytes += size_of::<LoxFunction>();
+        self.pending
+            .push(Pending::Environment(function.closure.clone()));
+    }
+    // 驻留的字符串被很多值共享，和其它对象一样只算一次
+    fn string(&mut self, s: &Symbol) {
+        if self.first_visit(s.as_ptr()) {
+            self.bytes += s.len();
+        }
+    }
+    // 字符串当场统计；引用类型放进待处理的栈（只复制 Rc）
+    fn visit(&mut self, object: &Object) {
+        match object {
+            Object::String(s) => self.string(s),
+            Object::Number(_) | Object::Boolean(_) | Object::NULL | Object::Uninitialized => {}
+            Object::Function(function) => self.pending.push(Pending::Function(function.clone())),
+            // 原生函数由宿主注册，不算在脚本头上
+            Object::Callable(_) => {}
+            _ => self.pending.push(Pending::Object(object.clone())),
+        }
+    }
+    fn object(&mut self, object: &Object) {
+        match object {
+            Object::Class(class) => {
+                if !self.first_visit(Rc::as_ptr(class)) {
+                    return;
+                }
+                self.bytes += size_of_val(class.as_ref()) + class.name.len();
+                for (name, method) in &class.methods {
+                    self.bytes += size_of::<Object>();
+                    self.string(name);
+                    self.visit(method);
+                }
+                if let Some(superclass) = &class.superclass {
+                    self.visit(&Object::Class(superclass.clone()));
+                }
+            }
+            Object::Instance(instance) => {
+                if !self.first_visit(Rc::as_ptr(instance)) {
+                    return;
+                }
+                let instance_ref = instance.borrow();
+                self.bytes += size_of_val(&*instance_ref);
+                for (name, value) in &instance_ref.fields {
+                    self.bytes += size_of::<Slot>();
+                    self.string(name);
+                    value.with(|value| self.visit(value));
+                }
+                self.visit(&Object::Class(instance_ref.class.clone()));
+            }
+            Object::List(list) => {
+                if !self.first_visit(Rc::as_ptr(list)) {
+                    return;
+                }
+                let list = list.borrow();
+                self.bytes += list.capacity() * size_of::<Slot>();
+                for element in list.iter() {
+                    element.with(|element| self.visit(element));
+                }
+            }
+            Object::Map(map) => {
+                if !self.first_visit(Rc::as_ptr(map)) {
+                    return;
+                }
+                let map = map.borrow();
+                for (key, value) in map.entries() {
+                    self.bytes += MAP_ENTRY_BYTES;
+                    self.visit(key.object());
+                    value.with(|value| self.visit(value));
+                }
+            }
+            Object::Closure(closure) => {
+                if !self.first_visit(Rc::as_ptr(closure)) {
+                    return;
+                }
+                // 字节码和语法树一样不算；还在栈上的上值已经随栈统计过
+                self.bytes += size_of::<Closure>()
+                    + closure.upvalues.len() * size_of::<Rc<RefCell<Upvalue>>>();
+                for upvalue in &closure.upvalues {
+                    if self.first_visit(Rc::as_ptr(upvalue))
+                        && let Upvalue::Closed(value) = &*upvalue.borrow()
+                    {
+                        self.bytes += size_of::<Slot>();
+                        value.with(|value| self.visit(value));
+                    }
+                }
+            }
+            Object::BoundMethod(bound) => {
+                if !self.first_visit(Rc::as_ptr(bound)) {
+                    return;
+                }
+                self.bytes += size_of::<BoundMethod>();
+                self.visit(&bound.receiver);
+                self.visit(&Object::Closure(bound.method.clone()));
+            }
+            // visit 只会把上面几种引用类型放进来
+            _ => {}
+        }
+    }
+}
+
+/// 映射里每个条目的大致开销：键和值各一个槽位，再加上索引表里的一份键和下标。
+pub const MAP_ENTRY_BYTES: usize = 2 * size_of::<Object>() + size_of::<Slot>() + size_of::<usize>();
+
+// 释放值时交给 release 的东西：值本身，或者环境链上的外层环境
+pub(crate) enum Garbage {
+    Object(Object),
+    Environment(Rc<RefCell<Environment>>),
+}
+impl Garbage {
+    fn frees_contents(&self) -> bool {
+        match self {
+            Garbage::Object(object) => frees_contents(object),
+            Garbage::Environment(environment) => Rc::strong_count(environment) == 1,
+        }
+    }
+}
+// 这是最后一份引用、释放时还要接着释放它里面的值时返回 true；其它情况直接释放只是减一次引用计数。
+// 容器的 Drop 先用它检查一遍，大多数时候（例如函数调用结束时只有数字的环境）不用经过 release
+pub(crate) fn frees_contents(object: &Object) -> bool {
+    match object {
+        Object::Function(f) => Rc::strong_count(f) == 1,
+        Object::Class(c) => Rc::strong_count(c) == 1,
+        Object::Instance(i) => Rc::strong_count(i) == 1,
+        Object::List(l) => Rc::strong_count(l) == 1,
+        Object::Map(m) => Rc::strong_count(m) == 1,
+        Object::Closure(c) => Rc::strong_count(c) == 1,
+        Object::BoundMethod(b) => Rc::strong_count(b) == 1,
+        _ => false,
+    }
+}
+impl From<Object> for Garbage {
+    fn from(object: Object) -> Self {
+        Garbage::Object(object)
+    }
+}
+
+thread_local! {
+    // 等待释放的值，以及是否已经有外层的 release 在逐个释放它们
+    static GARBAGE: RefCell<Vec<Garbage>> = const { RefCell::new(Vec::new()) };
+    static RELEASING: Cell<bool> = const { Cell::new(false) };
+}
+
+// 环境、实例、列表这些容器被释放时，把里面的值交给这里，而不是让 Rust 当场递归释放：
+// 值先放进待释放的栈，由最外层的调用逐个释放，释放它们时交回来的值同样进栈。
+// 这样释放很长的链表（例如 h = N(h) 或 l = [l] 循环上万次）也不会爆栈
+pub(crate) fn release(garbage: impl IntoIterator<Item = Garbage>) {
+    let mut garbage = garbage
+        .into_iter()
+        .filter(Garbage::frees_contents)
+        .peekable();
+    if garbage.peek().is_none() {
+        return;
+    }
+    // 线程退出、thread_local 已经销毁时只能直接释放
+    let Ok(outermost) = RELEASING.try_with(|releasing| !releasing.replace(true)) else {
+        return;
+    };
+    GARBAGE.with_borrow_mut(|pending| pending.extend(garbage));
+    if !outermost {
+        return;
+    }
+    while let Some(next) = GARBAGE.with_borrow_mut(Vec::pop) {
+        drop(next);
+    }
+    RELEASING.set(false);
+}
@@ -1,6 +1,9 @@
+use std::rc::Rc;
+
 use crate::{
-    expr::{Expr, Stmt},
-    report,
+    error::{At, LoxError},
+    expr::{Depth, Expr, FunctionDecl, Stmt},
+    symbol::Symbol,
     token::{Object, Token},
     token_type::TokenType,
 };
@@ -9,50 +12,139 @@ pub struct ParseError {
     pub message: String,
     pub line: usize,
     pub column: usize,
+    // 按 jlox 的习惯报告错误的记号，可能和上面的位置不同
+    pub at: At,
 }
 
 pub struct Parser {
     current: usize,
     tokens: Vec<Token>,
-    source_lines: Vec<String>,
+    // 当前所在的循环（由内到外依次压栈），元素是循环的标签；用来检查 break/continue
+    loops: Vec<Option<Symbol>>,
+    // 已经报告的错误；出错的声明被跳过，解析照常继续
+    errors: Vec<LoxError>,
+    // 兼容模式：表达式里的 { 不是映射字面量，和书中的 jlox 一样是语法错误
+    compat: bool,
 }
 impl Parser {
-    pub fn new(t: Vec<Token>, source: String) -> Self {
+    pub fn new(t: Vec<Token>) -> Self {
+        Self::new_with_compat(t, false)
+    }
+    pub fn new_with_compat(t: Vec<Token>, compat: bool) -> Self {
         Parser {
             current: 0,
             tokens: t,
-            source_lines: source.lines().map(String::from).collect(),
+            loops: Vec::new(),
+            errors: Vec::new(),
+            compat,
         }
     }
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ()> {
-        // 用 `()` 表示"有错误"，无额外信息
+    // 出错后同步到下一条语句继续解析，最后一并返回所有错误
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<LoxError>> {
         let mut statements = Vec::new();
-        let mut had_error = false; // 只需记录是否出错
-
         while !self.is_at_end() {
-            match self.declaration() {
-                Ok(stmt) => statements.push(stmt),
-                Err(err) => {
-                    report::report_error(err.line, &self.source_lines, err.column, &err.message);
-                    self.synchronize(); // 同步恢复
-                    had_error = true; // 标记出错
-                }
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
             }
         }
-
-        if had_error {
-            Err(()) // 返回简单错误标志
-        } else {
+        if self.errors.is_empty() {
             Ok(statements) // 全部成功
+        } else {
+            Err(std::mem::take(&mut self.errors))
         }
     }
 
-    fn declaration(&mut self) -> Result<Stmt, ParseError> {
+    // 和 jlox 一样在每条声明处恢复：块、函数体和类体里出错时跳过这一条，后面的照常解析，
+    // 不会因为剩下的 } 多报错误
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.try_declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(LoxError::from(err));
+                self.synchronize(); // 同步恢复
+                None
+            }
+        }
+    }
+    fn try_declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+        if self.match_token(&[TokenType::Fun]) {
+            return Ok(Stmt::Function(self.function("function")?));
+        }
         if self.match_token(&[TokenType::Var]) {
             return self.var_declaration();
         }
         self.statement()
     }
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, "Expect class name.")?
+            .clone();
+        let superclass = if self.match_token(&[TokenType::Less]) {
+            let superclass = self
+                .consume(TokenType::Identifier, "Expect superclass name.")?
+                .clone();
+            Some(Expr::Variable {
+                depth: Depth::default(),
+                name: superclass,
+            })
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        Ok(Stmt::Class {
+            name,
+            superclass,
+            methods,
+        })
+    }
+    // kind 用于错误信息，以后解析类的方法时也复用这个函数
+    fn function(&mut self, kind: &str) -> Result<Rc<FunctionDecl>, ParseError> {
+        let name = self
+            .consume(TokenType::Identifier, &format!("Expect {} name.", kind))?
+            .clone();
+        self.consume(
+            TokenType::LeftParen,
+            &format!("Expect '(' after {} name.", kind),
+        )?;
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                // 只报告，不中断解析
+                if params.len() >= 255 {
+                    self.report(self.peek().clone(), "Can't have more than 255 parameters.");
+                }
+                params.push(
+                    self.consume(TokenType::Identifier, "Expect parameter name.")?
+                        .clone(),
+                );
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expect '{{' before {} body.", kind),
+        )?;
+        // 函数体里不能 break/continue 到函数外面的循环
+        let enclosing_loops = std::mem::take(&mut self.loops);
+        let body = self.block_statement();
+        self.loops = enclosing_loops;
+        let body = match body? {
+            Stmt::Block { statements } => statements,
+            _ => unreachable!(),
+        };
+        Ok(Rc::new(FunctionDecl { name, params, body }))
+    }
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
         let name = self
             .consume(TokenType::Identifier, "Expect variable name.")?
@@ -79,14 +171,92 @@ impl Parser {
             return self.if_statement();
         }
         if self.match_token(&[TokenType::While]) {
-            return self.while_statement();
+            return self.while_statement(None);
         }
         if self.match_token(&[TokenType::For]) {
-            return self.for_statement();
+            return self.for_statement(None);
+        }
+        if self.match_token(&[TokenType::Break, TokenType::Continue]) {
+            return self.loop_control_statement();
+        }
+        // 带标签的循环：outer: while (...) ...
+        if self.check(TokenType::Identifier) && self.check_next(TokenType::Colon) {
+            let label = self.advance().clone();
+            self.advance();
+            if self.match_token(&[TokenType::While]) {
+                return self.while_statement(Some(label));
+            }
+            if self.match_token(&[TokenType::For]) {
+                return self.for_statement(Some(label));
+            }
+            let token = self.peek();
+            return Err(ParseError {
+                message: "Expect loop after label.".to_string(),
+                line: token.line,
+                column: token.column,
+                at: At::token(token),
+            });
+        }
+        if self.match_token(&[TokenType::Return]) {
+            return self.return_statement();
         }
         self.expression_statement()
     }
-    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let value = if !self.check(TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+        Ok(Stmt::Return { keyword, value })
+    }
+    fn loop_control_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let label = if self.match_token(&[TokenType::Identifier]) {
+            Some(self.previous().clone())
+        } else {
+            None
+        };
+        match &label {
+            None if self.loops.is_empty() => {
+                return Err(ParseError {
+                    message: format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+                    line: keyword.line,
+                    column: keyword.column,
+                    at: At::token(&keyword),
+                });
+            }
+            Some(name) if !self.loops.iter().any(|l| l.as_ref() == Some(&name.lexeme)) => {
+                return Err(ParseError {
+                    message: format!("No enclosing loop labeled '{}'.", name.lexeme),
+                    line: name.line,
+                    column: name.column,
+                    at: At::token(name),
+                });
+            }
+            _ => {}
+        }
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expect ';' after '{}'.", keyword.lexeme),
+        )?;
+        Ok(if keyword.token_type == TokenType::Break {
+            Stmt::Break { label }
+        } else {
+            Stmt::Continue { label }
+        })
+    }
+    // 解析循环体时把循环压栈，出错时也要弹出
+    fn loop_body(&mut self, label: &Option<Token>) -> Result<Stmt, ParseError> {
+        self.loops.push(label.as_ref().map(|l| l.lexeme.clone()));
+        let body = self.statement();
+        self.loops.pop();
+        body
+    }
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
 
         // 1. 解析初始化部分
@@ -109,25 +279,22 @@ impl Parser {
 
         // 3. 解析增量部分
         let increment = if !self.check(TokenType::RightParen) {
-            Some(self.expression()?)
+            Some(Box::new(self.expression()?))
         } else {
             None
         };
         self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
 
         // 4. 解析循环体
-        let mut body = self.statement()?;
-
-        // 5. 将 for 循环转换为 while 循环的结构
-        if let Some(increment) = increment {
-            body = Stmt::Block {
-                statements: vec![body, Stmt::Expression(increment)],
-            };
-        }
+        let body = self.loop_body(&label)?;
 
+        // 5. 将 for 循环转换为 while 循环的结构，增量部分交给 while 在每次迭代后执行
         let while_loop = Stmt::While {
+            keyword,
             condition,
             body: Box::new(body),
+            increment,
+            label,
         };
 
         // 6. 如果有初始化部分，包装在块语句中
@@ -139,12 +306,19 @@ impl Parser {
             while_loop
         })
     }
-    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
         let condition = self.expression()?;
-        self.consume(TokenType::RightParen, "Expect ')' after while condition.")?;
-        let body = Box::new(self.statement()?);
-        Ok(Stmt::While { condition, body })
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+        let body = Box::new(self.loop_body(&label)?);
+        Ok(Stmt::While {
+            keyword,
+            condition,
+            body,
+            increment: None,
+            label,
+        })
     }
     fn if_statement(&mut self) -> Result<Stmt, ParseError> {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
@@ -165,7 +339,9 @@ impl Parser {
     fn block_statement(&mut self) -> Result<Stmt, ParseError> {
         let mut statements = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
         }
         self.consume(TokenType::RightBrace, "Expect '}' after block.")?;
         Ok(Stmt::Block { statements })
@@ -176,27 +352,47 @@ impl Parser {
 
         // 2. 检查当前token是否是等号（表示这是一个赋值语句）
         if self.match_token(&[TokenType::Equal]) {
+            let equals = self.previous().clone(); // 获取等号token用于错误定位
             // 3. 递归解析等号右边的表达式
             let value = self.assignment()?;
-            let equals = self.previous(); // 获取等号token用于错误定位
 
             // 4. 检查左边表达式是否是变量（唯一合法的赋值目标）
             match expr {
-                Expr::Variable(name) => {
+                Expr::Variable { name, .. } => {
                     // 合法情况：创建赋值表达式节点
                     return Ok(Expr::Assign {
+                        depth: Depth::default(),
                         name,                   // 变量名
                         value: Box::new(value), // 要赋的值
                     });
                 }
-                _ => {
-                    // 非法情况：左边不是变量（如 `1+1 = 2` 这种非法语法）
-                    return Err(ParseError {
-                        message: "Invalid assignment target.".to_string(),
-                        line: equals.line,
-                        column: equals.column,
+                Expr::Get { object, name } => {
+                    // 属性赋值：a.b = c
+                    return Ok(Expr::Set {
+                        object,
+                        name,
+                        value: Box::new(value),
                     });
                 }
+                Expr::Index {
+                    object,
+                    bracket,
+                    index,
+                } => {
+                    // 下标赋值：xs[i] = v
+                    return Ok(Expr::SetIndex {
+                        object,
+                        bracket,
+                        index,
+                        value: Box::new(value),
+                    });
+                }
+                _ => {
+                    // 非法情况：左边不是变量（如 `1+1 = 2` 这种非法语法）。
+                    // 解析器并没有乱，只报告错误，不用同步
+                    self.report(equals, "Invalid assignment target.");
+                    return Ok(expr);
+                }
             }
         }
 
@@ -231,15 +427,28 @@ impl Parser {
     }
 
     fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         let value = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
-        Ok(Stmt::Print(value))
+        Ok(Stmt::Print {
+            keyword: Some(keyword),
+            expr: value,
+        })
     }
     fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
         Ok(Stmt::Expression(expr))
     }
+    // 记下一个不影响继续解析的错误
+    fn report(&mut self, token: Token, message: &str) {
+        self.errors.push(LoxError::from(ParseError {
+            message: message.to_string(),
+            line: token.line,
+            column: token.column,
+            at: At::token(&token),
+        }));
+    }
     //遇到解析错误（ParseError）后，让解析器"对齐"到下一个合理的语句起点，从而继续解析剩余代码，而不是整个程序直接中断。
     fn synchronize(&mut self) {
         self.advance();
@@ -258,10 +467,9 @@ impl Parser {
                 | TokenType::Return => {
                     return;
                 }
-                _ => {
-                    break;
-                }
+                _ => {}
             }
+            self.advance();
         }
     }
     fn expression(&mut self) -> Result<Expr, ParseError> {
@@ -283,6 +491,12 @@ impl Parser {
         }
         self.peek().token_type == t
     }
+    fn check_next(&self, t: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == t,
+            None => false,
+        }
+    }
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -337,6 +551,7 @@ impl Parser {
             TokenType::GreaterEqual,
             TokenType::Less,
             TokenType::LessEqual,
+            TokenType::In,
         ]) {
             let operator = self.previous().clone();
             let right_expr = self.term()?;
@@ -373,33 +588,162 @@ impl Parser {
             });
         }
 
-        self.primary() // 直接返回 primary() 的结果
+        self.call()
+    }
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+        // 支持连续调用、属性访问和下标，例如 f(1)(2)、a.b.c()、xs[0][1]
+        loop {
+            if self.match_token(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self
+                    .consume(TokenType::RightBracket, "Expect ']' after index.")?
+                    .clone();
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name = self
+                    .consume(TokenType::Identifier, "Expect property name after '.'.")?
+                    .clone();
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= 255 {
+                    self.report(self.peek().clone(), "Can't have more than 255 arguments.");
+                }
+                arguments.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        let paren = self
+            .consume(TokenType::RightParen, "Expect ')' after arguments.")?
+            .clone();
+        Ok(Expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        })
     }
     fn primary(&mut self) -> Result<Expr, ParseError> {
-        if self.match_token(&vec![TokenType::False]) {
+        if self.match_token(&[TokenType::False]) {
             return Ok(Expr::Literal(crate::token::Object::Boolean(false)));
         }
-        if self.match_token(&vec![TokenType::True]) {
+        if self.match_token(&[TokenType::True]) {
             return Ok(Expr::Literal(crate::token::Object::Boolean(true)));
         }
-        if self.match_token(&vec![TokenType::Nil]) {
+        if self.match_token(&[TokenType::Nil]) {
             return Ok(Expr::Literal(crate::token::Object::NULL));
         }
-        if self.match_token(&vec![TokenType::Number, TokenType::String]) {
+        if self.match_token(&[TokenType::Number, TokenType::String]) {
             let prev = self.previous();
             return match &prev.literal {
                 Object::Number(n) => Ok(Expr::Literal(Object::Number(*n))),
                 Object::String(s) => Ok(Expr::Literal(Object::String(s.clone()))),
                 _ => Err(ParseError {
-                    message: "Expected number or string literal at line".to_string(),
+                    message: "Expect expression.".to_string(),
                     line: prev.line,
                     column: prev.column,
+                    at: At::token(prev),
                 }),
             };
         }
+        if self.match_token(&[TokenType::Super]) {
+            let keyword = self.previous().clone();
+            self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+            let method = self
+                .consume(TokenType::Identifier, "Expect superclass method name.")?
+                .clone();
+            return Ok(Expr::Super {
+                depth: Depth::default(),
+                keyword,
+                method,
+            });
+        }
+        if self.match_token(&[TokenType::This]) {
+            return Ok(Expr::This {
+                depth: Depth::default(),
+                keyword: self.previous().clone(),
+            });
+        }
         if self.match_token(&[TokenType::Identifier]) {
             let name = self.previous().clone();
-            return Ok(Expr::Variable(name));
+            return Ok(Expr::Variable {
+                depth: Depth::default(),
+                name,
+            });
+        }
+        // 字符串插值 "a${x}b${y}c"：记号序列为
+        // Interpolation("a") x InterpolationEnd Interpolation("b") y InterpolationEnd String("c")
+        if self.match_token(&[TokenType::Interpolation]) {
+            let mut parts = Vec::new();
+            loop {
+                if let Object::String(s) = &self.previous().literal
+                    && !s.is_empty()
+                {
+                    parts.push(Expr::Literal(Object::String(s.clone())));
+                }
+                parts.push(self.expression()?);
+                self.consume(
+                    TokenType::InterpolationEnd,
+                    "Expect '}' after interpolation expression.",
+                )?;
+                if self.match_token(&[TokenType::Interpolation]) {
+                    continue;
+                }
+                let end = self.consume(TokenType::String, "Expect end of string interpolation.")?;
+                if let Object::String(s) = &end.literal
+                    && !s.is_empty()
+                {
+                    parts.push(Expr::Literal(Object::String(s.clone())));
+                }
+                return Ok(Expr::Interpolation(parts));
+            }
+        }
+        // 列表字面量，允许末尾多一个逗号
+        if self.match_token(&[TokenType::LeftBracket]) {
+            let mut elements = Vec::new();
+            while !self.check(TokenType::RightBracket) {
+                elements.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBracket, "Expect ']' after list elements.")?;
+            return Ok(Expr::List(elements));
+        }
+        // 映射字面量 {"a": 1, "b": 2}；语句开头的 { 已经被当作块，不会走到这里
+        if !self.compat && self.match_token(&[TokenType::LeftBrace]) {
+            let brace = self.previous().clone();
+            let mut entries = Vec::new();
+            while !self.check(TokenType::RightBrace) {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon, "Expect ':' after map key.")?;
+                let value = self.expression()?;
+                entries.push((key, value));
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expect '}' after map entries.")?;
+            return Ok(Expr::Map { brace, entries });
         }
         // 处理分组表达式
         if self.match_token(&[TokenType::LeftParen]) {
@@ -411,9 +755,10 @@ impl Parser {
         let token = self.peek();
 
         Err(ParseError {
-            message: "Expected expression at line".to_string(),
+            message: "Expect expression.".to_string(),
             line: token.line,
             column: token.column,
+            at: At::token(token),
         })
     }
 
@@ -432,13 +777,11 @@ impl Parser {
             // 获取上一个token的位置，这个位置更准确地表示了错误发生的地方
             let prev_token = self.previous();
 
-            // 构造更有信息量的错误消息
-            let error_message = format!("{} (found '{}' instead)", message, current_token.lexeme);
-
             Err(ParseError {
-                message: error_message,
+                message: message.to_string(),
                 line: prev_token.line, // 使用上一个token的位置
                 column: prev_token.column + prev_token.lexeme.len(), // 在上一个token之后
+                at: At::token(current_token),
             })
         }
     }
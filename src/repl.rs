@@ -0,0 +1,176 @@
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
+
+use crate::{
+    Lox,
+    line_editor::{LineEditor, ReadLine},
+};
+
+const PROMPT: &str = "> ";
+// 输入还没结束时的提示符
+const CONTINUATION_PROMPT: &str = "... ";
+
+/// 交互式解释器。所有输入共享同一个 [`Lox`]，前面定义的变量、函数和类后面都能用。
+pub struct Repl {
+    lox: Lox,
+    editor: LineEditor,
+}
+impl Repl {
+    /// 标准输入是终端时，历史保存在 `$HOME/.jlox_history`。
+    pub fn new() -> Self {
+        Self::new_with_lox(Lox::new())
+    }
+    /// 使用已经配置好的 [`Lox`]，历史的保存方式同上。
+    pub fn new_with_lox(lox: Lox) -> Self {
+        // 从管道或文件读入的不是手打的命令，不记进历史
+        let editor = match env::var_os("HOME") {
+            Some(home) if io::stdin().is_terminal() => {
+                LineEditor::new_with_history(PathBuf::from(home).join(".jlox_history"))
+            }
+            _ => LineEditor::new(),
+        };
+        Self::new_with(lox, editor)
+    }
+    pub fn new_with(lox: Lox, editor: LineEditor) -> Self {
+        Repl { lox, editor }
+    }
+    /// 一直读取并执行输入，直到 Ctrl-D 或输入结束。
+    pub fn run(&mut self) -> io::Result<()> {
+        let mut source = String::new();
+        loop {
+            let prompt = if source.is_empty() {
+                PROMPT
+            } else {
+                CONTINUATION_PROMPT
+            };
+            let line = match self.editor.read_line(prompt)? {
+                ReadLine::Line(line) => line,
+                // 放弃已经输入的所有行
+                ReadLine::Interrupted => {
+                    source.clear();
+                    continue;
+                }
+                ReadLine::Eof => return Ok(()),
+            };
+            self.editor.add_history(&line);
+            source.push_str(&line);
+            source.push('\n');
+            if !is_complete(&source) {
+                continue;
+            }
+            let input = std::mem::take(&mut source);
+            if input.trim().is_empty() {
+                continue;
+            }
+            // 错误已经打印到诊断输出，继续读下一条
+            let _ = self.lox.run_interactive(&with_semicolon(&input));
+        }
+    }
+}
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 判断输入是否完整：字符串都已闭合，花括号（包括 `${` 插值）也都已配对。
+///
+/// 多出来的 `}` 算作完整，交给语法分析报错。
+pub fn is_complete(source: &str) -> bool {
+    let mut chars = source.chars().peekable();
+    // 代码里未闭合的 { 个数
+    let mut depth = 0usize;
+    // 每个尚未结束的 ${ 进入插值时外面的 depth
+    let mut interpolations: Vec<usize> = Vec::new();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '"' => in_string = false,
+                '$' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    interpolations.push(depth);
+                    in_string = false;
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => {
+                if interpolations.last() == Some(&depth) {
+                    interpolations.pop();
+                    in_string = true;
+                } else {
+                    depth = depth.saturating_sub(1);
+                }
+            }
+            // 注释一直到行尾，里面的引号和括号不算
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    !in_string && depth == 0 && interpolations.is_empty()
+}
+
+// 单独一行的表达式可以省略结尾的分号，例如直接输入 `1 + 2`
+fn with_semicolon(input: &str) -> String {
+    let trimmed = input.trim_end();
+    if trimmed.ends_with(';') || trimmed.ends_with('}') {
+        input.to_string()
+    } else {
+        format!("{};", trimmed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_complete;
+
+    #[test]
+    fn complete_input() {
+        for source in [
+            "",
+            "print 1;\n",
+            "fun f() {\n  return 1;\n}\n",
+            "print \"{\";\n",
+            "print \"a\\\"b\";\n",
+            "print \"${ {\"k\": 1}[\"k\"] }\";\n",
+            "print \"${ \"${1}\" }\";\n",
+            "// {\n",
+            "}\n",
+        ] {
+            assert!(is_complete(source), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn incomplete_input() {
+        for source in [
+            "fun f() {\n",
+            "{ {\n}\n",
+            "print \"abc\n",
+            "print \"a\\\"\n",
+            "print \"${1 + \n",
+            "print \"${ \"${1}\n",
+            "print \"${1}\n",
+            "{ // }\n",
+        ] {
+            assert!(!is_complete(source), "{:?}", source);
+        }
+    }
+}
@@ -1,34 +1,123 @@
-pub fn report_error_multiline(
-    start_line: usize,
-    context_lines: &[String],
-    start_column: usize,
-    message: &str,
-) {
-    eprintln!("Error: {}", message);
-    eprintln!();
-
-    // 打印所有相关行
-    for (i, line) in context_lines.iter().enumerate() {
-        eprintln!("{:>4} | {}", start_line + i, line);
-
-        // 只在第一行（字符串开始的地方）显示错误指示符
-        if i == 0 {
-            eprintln!(
-                "     | {}{}",
-                " ".repeat(start_column - 1),
-                "^-- String starts here"
-            );
+use std::{cell::RefCell, io::Write, rc::Rc};
+
+use crate::error::{Diagnostic, LoxError};
+
+/// 输出目标：程序输出或诊断信息写到这里。
+///
+/// 用 `Rc<RefCell<..>>` 共享，调用方可以保留一份引用，之后读出写入的内容，例如：
+/// `let buffer = Rc::new(RefCell::new(Vec::<u8>::new())); let sink: Sink = buffer.clone();`
+pub type Sink = Rc<RefCell<dyn Write>>;
+
+pub fn stdout() -> Sink {
+    Rc::new(RefCell::new(std::io::stdout()))
+}
+pub fn stderr() -> Sink {
+    Rc::new(RefCell::new(std::io::stderr()))
+}
+
+// 把一条错误连同出错的源码行一起打印出来，诊断信息写失败（例如管道已关闭）时
+// 没有更好的地方可以报告，直接忽略
+pub fn render(out: &Sink, error: &LoxError, source_lines: &[String]) {
+    render_diagnostic(out, error.kind(), error.diagnostic(), source_lines);
+}
+
+/// 打印一条警告（[`crate::Severity::Warning`]），格式和错误一样，以 `Warning:` 开头。
+pub fn render_warning(out: &Sink, warning: &Diagnostic, source_lines: &[String]) {
+    render_diagnostic(out, "Warning", warning, source_lines);
+}
+
+fn render_diagnostic(out: &Sink, heading: &str, diagnostic: &Diagnostic, source_lines: &[String]) {
+    let span = diagnostic.span;
+    let mut out = out.borrow_mut();
+    let _ = match &diagnostic.at {
+        Some(at) => writeln!(out, "{} at {}: {}", heading, at, diagnostic.message),
+        None => writeln!(out, "{}: {}", heading, diagnostic.message),
+    };
+    if let Some(file) = &diagnostic.file {
+        let _ = writeln!(out, "  --> {}:{}:{}", file, span.line, span.column);
+    }
+    // 行号超出源码范围（例如原生函数报的错没有位置）时不打印源码
+    if span.line != 0 && span.line <= source_lines.len() {
+        let _ = writeln!(out);
+        let end_line = span.end_line.clamp(span.line, source_lines.len());
+        for line in span.line..=end_line {
+            let _ = writeln!(out, "{:>4} | {}", line, source_lines[line - 1]);
+            // 只在开始的那一行标出位置
+            if line == span.line {
+                let _ = writeln!(
+                    out,
+                    "     | {}^-- Here.",
+                    " ".repeat(span.column.saturating_sub(1))
+                );
+            }
+        }
+    }
+    if !diagnostic.trace.is_empty() {
+        let _ = writeln!(out);
+        let _ = writeln!(out, "Stack trace (most recent call last):");
+        // 连续相同的帧（通常是无限递归）只打印前几个，剩下的合成一行
+        let mut repeated = 0;
+        for (i, frame) in diagnostic.trace.iter().enumerate() {
+            if i > 0 && diagnostic.trace[i - 1] == *frame {
+                repeated += 1;
+            } else {
+                write_repeated(&mut *out, repeated);
+                repeated = 0;
+            }
+            if repeated >= MAX_REPEATED_FRAMES {
+                continue;
+            }
+            let _ = match (&frame.file, frame.line) {
+                (_, 0) => writeln!(out, "  at {}", frame.name),
+                (Some(file), line) => {
+                    writeln!(
+                        out,
+                        "  at {} ({}:{}:{})",
+                        frame.name, file, line, frame.column
+                    )
+                }
+                (None, line) => writeln!(out, "  at {} ({}:{})", frame.name, line, frame.column),
+            };
         }
+        write_repeated(&mut *out, repeated);
     }
-    // 在最后一行后面显示 "never closes" 提示
-    eprintln!("     | {}", "... string never closes");
 }
 
-pub fn report_error(line: usize, source_lines: &[String], column: usize, message: &str) {
-    if let Some(line_content) = source_lines.get(line - 1) {
-        eprintln!("Error: {}", message);
-        eprintln!();
-        eprintln!("{:>4} | {}", line, line_content);
-        eprintln!("     | {}{}", " ".repeat(column - 1), "^-- Here.");
+// 一帧连续出现超过这么多次后不再逐个打印
+const MAX_REPEATED_FRAMES: usize = 3;
+
+// repeated 是和上一帧相同的帧数，其中超出 MAX_REPEATED_FRAMES 的部分没有打印
+fn write_repeated(out: &mut dyn Write, repeated: usize) {
+    if repeated >= MAX_REPEATED_FRAMES {
+        let _ = writeln!(
+            out,
+            "  [previous line repeated {} more times]",
+            repeated + 1 - MAX_REPEATED_FRAMES
+        );
     }
 }
+
+// 和 jlox 完全一致的输出，用于官方测试集：
+// 编译错误是 `[line N] Error at 'x': ...`，运行时错误是消息后面跟一行 `[line N]`
+pub fn render_compat(out: &Sink, error: &LoxError) {
+    let diagnostic = error.diagnostic();
+    let mut out = out.borrow_mut();
+    let _ = match (error, &diagnostic.at) {
+        (LoxError::Runtime(_) | LoxError::Limit(..), _) => writeln!(
+            out,
+            "{}\n[line {}]",
+            diagnostic.message, diagnostic.span.line
+        ),
+        (_, Some(at)) => writeln!(
+            out,
+            "[line {}] Error at {}: {}",
+            at.line, at, diagnostic.message
+        ),
+        // jlox 在扫描到出错的地方才报告，未闭合的字符串报在文件末尾那一行
+        (_, None) => writeln!(
+            out,
+            "[line {}] Error: {}",
+            diagnostic.span.end_line, diagnostic.message
+        ),
+    };
+}
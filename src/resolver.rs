@@ -0,0 +1,296 @@
+use crate::{
+    error::{At, Diagnostic, LoxError, Span},
+    expr::{Depth, Expr, FunctionDecl, Stmt},
+    symbol::{Symbol, SymbolMap},
+    token::Token,
+};
+
+// 当前所在的函数类型，用来检查 return 的位置
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Initializer,
+    Method,
+}
+// 当前所在的类，用来检查 this 和 super 的位置
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+// 在解析之后、执行之前遍历一次语法树：
+// 算出每个局部变量引用离定义处隔了几层作用域，记在语法树的节点上，同时报告静态错误
+pub struct Resolver {
+    // 只记录局部作用域，全局变量不在这里；值表示变量是否已经初始化完毕
+    scopes: Vec<SymbolMap<bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: Vec<LoxError>,
+}
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            errors: Vec::new(),
+        }
+    }
+    // 遇到错误时继续遍历，最后一并返回所有错误
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Result<(), Vec<LoxError>> {
+        self.resolve_stmts(statements);
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+    fn resolve_stmts(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.resolve_stmt(stmt);
+        }
+    }
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(expr) | Stmt::Print { expr, .. } => self.resolve_expr(expr),
+            Stmt::Var { name, initializer } => {
+                // 先声明再定义，这样初始化表达式里引用自己时能发现
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expr(initializer);
+                }
+                self.define(name);
+            }
+            Stmt::Block { statements } => {
+                self.begin_scope();
+                self.resolve_stmts(statements);
+                self.end_scope();
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            }
+            Stmt::While {
+                condition,
+                body,
+                increment,
+                ..
+            } => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(body);
+                if let Some(increment) = increment {
+                    self.resolve_expr(increment);
+                }
+            }
+            Stmt::Break { .. } | Stmt::Continue { .. } => {}
+            Stmt::Function(declaration) => {
+                // 函数名先定义，函数体里才能递归调用自己
+                self.declare(&declaration.name);
+                self.define(&declaration.name);
+                self.resolve_function(declaration, FunctionType::Function);
+            }
+            Stmt::Return { keyword, value } => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, "Can't return from top-level code.");
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(keyword, "Can't return a value from an initializer.");
+                    }
+                    self.resolve_expr(value);
+                }
+            }
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if let Expr::Variable {
+                        name: superclass_name,
+                        ..
+                    } = superclass
+                        && superclass_name.lexeme == name.lexeme
+                    {
+                        self.error(superclass_name, "A class can't inherit from itself.");
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expr(superclass);
+                    // 和解释器一致：方法外面多一层只有 super 的作用域
+                    self.begin_scope();
+                    self.declare_internal("super");
+                }
+
+                self.begin_scope();
+                self.declare_internal("this");
+                for method in methods {
+                    let declaration = if method.name.lexeme == "init" {
+                        FunctionType::Initializer
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.resolve_function(method, declaration);
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.current_class = enclosing_class;
+            }
+        }
+    }
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Unary { right, .. } => self.resolve_expr(right),
+            Expr::Grouping(expr) => self.resolve_expr(expr),
+            Expr::Literal(_) => {}
+            Expr::Variable { depth, name } => {
+                if let Some(scope) = self.scopes.last()
+                    && scope.get(&name.lexeme) == Some(&false)
+                {
+                    self.error(name, "Can't read local variable in its own initializer.");
+                }
+                self.resolve_local(depth, name);
+            }
+            Expr::Assign { depth, name, value } => {
+                self.resolve_expr(value);
+                self.resolve_local(depth, name);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.resolve_expr(callee);
+                for argument in arguments {
+                    self.resolve_expr(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.resolve_expr(object),
+            Expr::Set { object, value, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(object);
+            }
+            Expr::Interpolation(elements) | Expr::List(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::SetIndex {
+                object,
+                index,
+                value,
+                ..
+            } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+                self.resolve_expr(value);
+            }
+            Expr::This { depth, keyword } => {
+                if self.current_class == ClassType::None {
+                    self.error(keyword, "Can't use 'this' outside of a class.");
+                    return;
+                }
+                self.resolve_local(depth, keyword);
+            }
+            Expr::Super { depth, keyword, .. } => {
+                match self.current_class {
+                    ClassType::None => {
+                        self.error(keyword, "Can't use 'super' outside of a class.");
+                    }
+                    ClassType::Class => {
+                        self.error(keyword, "Can't use 'super' in a class with no superclass.");
+                    }
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(depth, keyword);
+            }
+        }
+    }
+    fn resolve_function(&mut self, function: &FunctionDecl, function_type: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_stmts(&function.body);
+        self.end_scope();
+        self.current_function = enclosing_function;
+    }
+    fn begin_scope(&mut self) {
+        self.scopes.push(SymbolMap::default());
+    }
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if scope.contains_key(&name.lexeme) {
+            self.error(name, "Already a variable with this name in this scope.");
+            return;
+        }
+        scope.insert(name.lexeme.clone(), false);
+    }
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+    // this 和 super 这类由解释器隐式定义的名字
+    fn declare_internal(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(Symbol::from(name), true);
+        }
+    }
+    // 从内向外找变量所在的作用域；找不到就当作全局变量，运行时再查
+    fn resolve_local(&mut self, depth: &Depth, name: &Token) {
+        for (i, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&name.lexeme) {
+                depth.set(Some(i));
+                return;
+            }
+        }
+    }
+    fn error(&mut self, token: &Token, message: &str) {
+        let mut diagnostic = Diagnostic::error(Span::token(token), message);
+        diagnostic.at = Some(At::token(token));
+        self.errors.push(LoxError::Resolution(diagnostic));
+    }
+}
@@ -1,30 +1,53 @@
+use std::{iter::Peekable, str::Chars};
+
 use crate::{
-    report,
+    error::{Diagnostic, LoxError, Span},
+    symbol::Symbol,
     token::{Object, Token},
     token_type::TokenType,
 };
-#[derive(Debug)]
 pub struct Scanner {
     line: usize,
     column: usize,
-    source_lines: Vec<String>,
+    errors: Vec<LoxError>,
+    // 尚未结束的 ${ 插值：(插值表达式里未闭合的 { 个数, ${ 所在行, 列)
+    interpolations: Vec<(usize, usize, usize)>,
+    // 兼容模式：只认书中 jlox 的语法，没有列表、映射、标签、break/continue/in、转义和插值
+    compat: bool,
 }
 
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl Scanner {
     pub fn new() -> Self {
+        Self::new_with_compat(false)
+    }
+    pub fn new_with_compat(compat: bool) -> Self {
         Scanner {
             line: 1,
             column: 0,
-            source_lines: Vec::new(),
+            errors: Vec::new(),
+            interpolations: Vec::new(),
+            compat,
+        }
+    }
+    // 遇到错误时继续扫描，最后一并返回所有错误
+    pub fn scan_tokens(&mut self, source: String) -> Result<Vec<Token>, Vec<LoxError>> {
+        let (tokens, errors) = self.scan_all(source);
+        if errors.is_empty() {
+            Ok(tokens)
+        } else {
+            Err(errors)
         }
     }
-    pub fn scan_tokens(&mut self, source: String) -> Result<Vec<Token>, ()> {
+    // 和 jlox 一样，出错时也返回扫描出来的记号，语法分析可以接着报告后面的错误
+    pub fn scan_all(&mut self, source: String) -> (Vec<Token>, Vec<LoxError>) {
         self.column = 0;
-        // 在开始扫描前，先把源代码按行分割并存储
-        self.source_lines = source.lines().map(String::from).collect();
         let mut tokens = Vec::new();
         let mut chars = source.chars().peekable();
-        let mut had_error = false;
         while let Some(c) = chars.next() {
             self.column += 1;
             match c {
@@ -47,6 +70,9 @@ impl Scanner {
                     ));
                 }
                 '{' => {
+                    if let Some((depth, _, _)) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
                     tokens.push(Token::new(
                         TokenType::LeftBrace,
                         c.to_string(),
@@ -56,8 +82,46 @@ impl Scanner {
                     ));
                 }
                 '}' => {
+                    match self.interpolations.last_mut() {
+                        // 插值表达式结束，回到字符串里继续扫描
+                        Some((0, _, _)) => {
+                            self.interpolations.pop();
+                            tokens.push(Token::new(
+                                TokenType::InterpolationEnd,
+                                c.to_string(),
+                                Object::NULL,
+                                self.line,
+                                self.column,
+                            ));
+                            let (line, column) = (self.line, self.column);
+                            self.scan_string(&mut chars, &mut tokens, line, column);
+                        }
+                        depth => {
+                            if let Some((depth, _, _)) = depth {
+                                *depth -= 1;
+                            }
+                            tokens.push(Token::new(
+                                TokenType::RightBrace,
+                                c.to_string(),
+                                Object::NULL,
+                                self.line,
+                                self.column,
+                            ));
+                        }
+                    }
+                }
+                '[' if !self.compat => {
                     tokens.push(Token::new(
-                        TokenType::RightBrace,
+                        TokenType::LeftBracket,
+                        c.to_string(),
+                        Object::NULL,
+                        self.line,
+                        self.column,
+                    ));
+                }
+                ']' if !self.compat => {
+                    tokens.push(Token::new(
+                        TokenType::RightBracket,
                         c.to_string(),
                         Object::NULL,
                         self.line,
@@ -73,6 +137,15 @@ impl Scanner {
                         self.column,
                     ));
                 }
+                ':' if !self.compat => {
+                    tokens.push(Token::new(
+                        TokenType::Colon,
+                        c.to_string(),
+                        Object::NULL,
+                        self.line,
+                        self.column,
+                    ));
+                }
                 '.' => {
                     tokens.push(Token::new(
                         TokenType::Dot,
@@ -245,53 +318,11 @@ impl Scanner {
                     self.column = 0;
                 }
                 '"' => {
-                    let start_line = self.line; // 记录字符串开始时的行号
-                    let start_column = self.column;
-                    let mut string_content = String::new();
-                    let mut current_line = self.line; // 用于跟踪当前扫描到的行
-
-                    // 处理字符串内容
-                    while let Some(&next_char) = chars.peek() {
-                        if next_char == '"' {
-                            break; // 找到闭合引号
-                        }
-                        if next_char == '\n' {
-                            current_line += 1;
-                            self.line += 1;
-                            self.column = 0;
-                        }
-                        string_content.push(chars.next().unwrap());
-                        self.column += 1;
-                    }
-
-                    // 检查是否到达文件末尾而未闭合
-                    if chars.peek().is_none() {
-                        // 构建包含所有相关行的错误上下文
-                        let context_lines: Vec<String> = (start_line..=current_line)
-                            .filter_map(|line_num| self.source_lines.get(line_num - 1).cloned())
-                            .collect();
-
-                        report::report_error_multiline(
-                            start_line,     // 字符串开始的行号
-                            &context_lines, // 所有相关行的内容
-                            start_column,   // 字符串开始的列号
-                            "Unterminated string - string starts here and never closes",
-                        );
-                        had_error = true;
-                    } else {
-                        // 消费闭合引号
-                        chars.next();
-                        self.column += 1;
-                        tokens.push(Token::new(
-                            TokenType::String,
-                            string_content.clone(),
-                            Object::String(string_content),
-                            start_line,
-                            start_column,
-                        ));
-                    }
+                    let (line, column) = (self.line, self.column);
+                    self.scan_string(&mut chars, &mut tokens, line, column);
                 }
                 c if c.is_ascii_digit() => {
+                    let start_column = self.column;
                     let mut number_literal = c.to_string();
 
                     // 收集整数部分
@@ -304,20 +335,19 @@ impl Scanner {
                         }
                     }
                     // 查看是否存在小数部分
-                    if let Some(&'.') = chars.peek() {
-                        if let Some(next_next_char) = chars.clone().nth(1) {
-                            if next_next_char.is_ascii_digit() {
-                                number_literal.push(chars.next().unwrap()); // 消费 '.'
+                    if let Some(&'.') = chars.peek()
+                        && let Some(next_next_char) = chars.clone().nth(1)
+                        && next_next_char.is_ascii_digit()
+                    {
+                        number_literal.push(chars.next().unwrap()); // 消费 '.'
+                        self.column += 1;
+                        // 收集小数部分
+                        while let Some(&next_char) = chars.peek() {
+                            if next_char.is_ascii_digit() {
+                                number_literal.push(chars.next().unwrap());
                                 self.column += 1;
-                                // 收集小数部分
-                                while let Some(&next_char) = chars.peek() {
-                                    if next_char.is_ascii_digit() {
-                                        number_literal.push(chars.next().unwrap());
-                                        self.column += 1;
-                                    } else {
-                                        break;
-                                    }
-                                }
+                            } else {
+                                break;
                             }
                         }
                     }
@@ -329,21 +359,22 @@ impl Scanner {
                                 number_literal.clone(),
                                 Object::Number(value),
                                 self.line,
-                                self.column,
+                                start_column,
                             ));
                         }
-                        Err(_) => {
-                            report::report_error(
-                                self.line,
-                                &self.source_lines,
-                                self.column,
-                                "Invalid number literal.",
-                            );
-                            had_error = true;
-                        }
+                        Err(_) => self.error(
+                            Span {
+                                line: self.line,
+                                column: start_column,
+                                end_line: self.line,
+                                end_column: self.column,
+                            },
+                            "Invalid number literal.",
+                        ),
                     }
                 }
                 c if Scanner::is_alpha(c) => {
+                    let start_column = self.column;
                     let mut identifier = c.to_string();
 
                     // 收集后续的字母数字字符
@@ -357,13 +388,17 @@ impl Scanner {
                     }
                     // 检查是否是关键字
                     let token_type = match identifier.as_str() {
+                        "break" | "continue" | "in" if self.compat => TokenType::Identifier,
                         "and" => TokenType::And,
+                        "break" => TokenType::Break,
                         "class" => TokenType::Class,
+                        "continue" => TokenType::Continue,
                         "else" => TokenType::Else,
                         "false" => TokenType::False,
                         "fun" => TokenType::Fun,
                         "for" => TokenType::For,
                         "if" => TokenType::If,
+                        "in" => TokenType::In,
                         "nil" => TokenType::Nil,
                         "or" => TokenType::Or,
                         "print" => TokenType::Print,
@@ -381,21 +416,26 @@ impl Scanner {
                         identifier,
                         Object::NULL,
                         self.line,
-                        self.column,
+                        start_column,
                     ));
                 }
 
-                _ => {
-                    report::report_error(
-                        self.line,
-                        &self.source_lines,
-                        self.column,
-                        "Unexpected character.",
-                    );
-                    had_error = true;
-                }
+                _ => self.error(Span::point(self.line, self.column), "Unexpected character."),
             }
         }
+        // 还有没闭合的 ${ ，报告在最外层那个的位置
+        if let Some(&(_, line, column)) = self.interpolations.first() {
+            self.error(
+                Span {
+                    line,
+                    column,
+                    end_line: line,
+                    end_column: column + 1,
+                },
+                "Unterminated string interpolation.",
+            );
+            self.interpolations.clear();
+        }
         tokens.push(Token::new(
             TokenType::Eof,
             "".to_string(),
@@ -403,14 +443,158 @@ impl Scanner {
             self.line,
             self.column,
         ));
-        if had_error { Err(()) } else { Ok(tokens) }
+        (tokens, std::mem::take(&mut self.errors))
+    }
+    fn error(&mut self, span: Span, message: &str) {
+        self.errors
+            .push(LoxError::Lexical(Diagnostic::error(span, message)));
+    }
+
+    // 扫描字符串内容，直到闭合引号或者 ${ 。调用时开头的 " 或插值结尾的 } 已经被消费。
+    // 遇到 ${ 时产生 Interpolation 记号，插值表达式按普通代码扫描，
+    // 对应的 } 出现后再回到这里继续
+    fn scan_string(
+        &mut self,
+        chars: &mut Peekable<Chars<'_>>,
+        tokens: &mut Vec<Token>,
+        start_line: usize,
+        start_column: usize,
+    ) {
+        let mut string_content = String::new();
+        loop {
+            match chars.peek().copied() {
+                None => {
+                    // 到达文件末尾而未闭合，范围从字符串开头一直到文件末尾
+                    self.error(
+                        Span {
+                            line: start_line,
+                            column: start_column,
+                            end_line: self.line,
+                            end_column: self.column.max(1),
+                        },
+                        "Unterminated string.",
+                    );
+                    return;
+                }
+                Some('"') => {
+                    // 消费闭合引号
+                    chars.next();
+                    self.column += 1;
+                    tokens.push(Token::new(
+                        TokenType::String,
+                        string_content.clone(),
+                        Object::String(Symbol::from(string_content)),
+                        start_line,
+                        start_column,
+                    ));
+                    return;
+                }
+                Some('$') if !self.compat && chars.clone().nth(1) == Some('{') => {
+                    chars.next();
+                    chars.next();
+                    self.column += 2;
+                    tokens.push(Token::new(
+                        TokenType::Interpolation,
+                        string_content.clone(),
+                        Object::String(Symbol::from(string_content)),
+                        start_line,
+                        start_column,
+                    ));
+                    self.interpolations.push((0, self.line, self.column - 1));
+                    return;
+                }
+                Some('\\') if !self.compat => {
+                    chars.next();
+                    self.column += 1;
+                    let escape_column = self.column;
+                    match Self::scan_escape(chars, &mut self.column) {
+                        Ok(Some(c)) => string_content.push(c),
+                        Ok(None) => {} // 反斜杠后面就是文件末尾，下一轮报告未闭合
+                        Err(message) => self.error(
+                            Span {
+                                line: self.line,
+                                column: escape_column,
+                                end_line: self.line,
+                                end_column: self.column,
+                            },
+                            &message,
+                        ),
+                    }
+                }
+                Some('\n') => {
+                    string_content.push(chars.next().unwrap());
+                    self.line += 1;
+                    self.column = 0;
+                }
+                Some(_) => {
+                    string_content.push(chars.next().unwrap());
+                    self.column += 1;
+                }
+            }
+        }
+    }
+    // 处理反斜杠后面的部分，返回转义得到的字符
+    fn scan_escape(
+        chars: &mut Peekable<Chars<'_>>,
+        column: &mut usize,
+    ) -> Result<Option<char>, String> {
+        let c = match chars.peek() {
+            None => return Ok(None),
+            // 换行留给外层处理行号
+            Some('\n') => return Err("Invalid escape sequence at end of line.".to_string()),
+            Some(&c) => c,
+        };
+        chars.next();
+        *column += 1;
+        let escaped = match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '\\' => '\\',
+            '$' => '$',
+            'u' => {
+                // \u{XXXX}：1 到 6 位十六进制数
+                if chars.peek() != Some(&'{') {
+                    return Err("Expect '{' after '\\u'.".to_string());
+                }
+                chars.next();
+                *column += 1;
+                let mut hex = String::new();
+                while let Some(&next_char) = chars.peek() {
+                    if !next_char.is_ascii_hexdigit() {
+                        break;
+                    }
+                    hex.push(next_char);
+                    chars.next();
+                    *column += 1;
+                }
+                if chars.peek() != Some(&'}') {
+                    return Err("Expect '}' after unicode escape digits.".to_string());
+                }
+                chars.next();
+                *column += 1;
+                if hex.is_empty() || hex.len() > 6 {
+                    return Err(format!("Invalid unicode escape '\\u{{{}}}'.", hex));
+                }
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) => c,
+                    None => {
+                        return Err(format!("Invalid unicode escape '\\u{{{}}}'.", hex));
+                    }
+                }
+            }
+            _ => return Err(format!("Invalid escape sequence '\\{}'.", c)),
+        };
+        Ok(Some(escaped))
     }
 
     fn is_alpha(c: char) -> bool {
-        return (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c == '_';
+        c.is_ascii_lowercase() || c.is_ascii_uppercase() || c == '_'
     }
 
     fn is_alpha_numeric(c: char) -> bool {
-        return Self::is_alpha(c) || c.is_ascii_digit();
+        Self::is_alpha(c) || c.is_ascii_digit()
     }
 }
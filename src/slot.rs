@@ -0,0 +1,367 @@
+//! 存放值的槽位：变量、字段、列表元素、映射的值、上值和字节码虚拟机的栈都用它。
+//!
+//! 默认直接存 [`Object`]，每个槽位 24 字节。打开 `nan-boxing` 特性后改用 NaN-boxing：
+//! 每个槽位只有 8 字节，数字按 f64 原样存放，其它值塞进 f64 的 NaN 空间里——
+//! 符号位和三个标签位区分类型，低 48 位是 nil、true 这些常量或者对象的地址。
+//! 两种表示的行为完全一样，只有内存占用和速度不同，`bench.sh` 里有对比。
+//!
+//! ```
+//! use jlox::{Object, slot::Slot};
+//!
+//! let slot = Slot::new(Object::Number(1.5));
+//! assert!(matches!(slot.get(), Object::Number(n) if n == 1.5));
+//! let slot = Slot::new(Object::String("abc".into()));
+//! assert!(matches!(slot.clone().get(), Object::String(s) if s == "abc"));
+//! ```
+use std::fmt;
+
+#[cfg(not(feature = "nan-boxing"))]
+use crate::memory::{self, Garbage};
+use crate::token::Object;
+
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Clone)]
+pub struct Slot(Object);
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Slot {
+    pub fn new(value: Object) -> Self {
+        Slot(value)
+    }
+    /// 取出槽位里的值的一份拷贝。
+    pub fn get(&self) -> Object {
+        self.0.clone()
+    }
+    /// 把值从槽位里拿出来，不改变引用计数。
+    pub fn into_object(mut self) -> Object {
+        std::mem::replace(&mut self.0, Object::NULL)
+    }
+    /// 借用槽位里的值，不改变引用计数。
+    pub fn with<R>(&self, f: impl FnOnce(&Object) -> R) -> R {
+        f(&self.0)
+    }
+}
+// 值里的容器交给 memory::release，列表套列表这样的长链不在这里递归释放
+#[cfg(not(feature = "nan-boxing"))]
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if !memory::frees_contents(&self.0) {
+            return;
+        }
+        memory::release([Garbage::Object(std::mem::replace(
+            &mut self.0,
+            Object::NULL,
+        ))]);
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+pub use nan_boxing::Slot;
+
+impl From<Object> for Slot {
+    fn from(value: Object) -> Self {
+        Slot::new(value)
+    }
+}
+impl fmt::Debug for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.get(), f)
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+mod nan_boxing {
+    use std::{cell::RefCell, mem::ManuallyDrop, rc::Rc};
+
+    use crate::{
+        lox_callable::LoxCallable,
+        lox_class::LoxClass,
+        lox_function::LoxFunction,
+        lox_instance::LoxInstance,
+        lox_map::LoxMap,
+        memory::{self, Garbage},
+        symbol::{Interned, Symbol},
+        token::Object,
+        vm::{BoundMethod, Closure},
+    };
+
+    // 指数全为 1 并且最高尾数位为 1 的 quiet NaN。不是 NaN 的数字都不会落在这个空间里，
+    // 计算得到的 NaN 存进来之前统一换成 CANONICAL_NAN
+    const QNAN: u64 = 0x7ff8_0000_0000_0000;
+    const SIGN: u64 = 1 << 63;
+    const CANONICAL_NAN: u64 = QNAN;
+    const PAYLOAD: u64 = (1 << 48) - 1;
+
+    // 类型编号：符号位是编号的第 4 位，第 48～50 位是低 3 位。编号 0 的载荷是下面这些常量，
+    // 其余编号的载荷是 Rc::into_raw 得到的地址，槽位持有它的一份强引用
+    const CONSTANT: u64 = 0;
+    const NIL: u64 = 1;
+    const FALSE: u64 = 2;
+    const TRUE: u64 = 3;
+    const UNINITIALIZED: u64 = 4;
+    // 对象的类型编号
+    const STRING: u64 = 1;
+    const CALLABLE: u64 = 2;
+    const FUNCTION: u64 = 3;
+    const CLASS: u64 = 4;
+    const INSTANCE: u64 = 5;
+    const LIST: u64 = 6;
+    const MAP: u64 = 7;
+    const CLOSURE: u64 = 8;
+    const BOUND_METHOD: u64 = 9;
+
+    /// NaN-boxing 表示的槽位，8 字节。
+    pub struct Slot(u64);
+
+    // 每种对象的编号、地址指向的类型、存进槽位时怎么换成地址、取出时怎么从地址换回值。
+    // 换成地址时槽位接管值的一份引用，换回值时按位读出这份引用，不释放任何内存。
+    // 原生函数是胖指针，放不进 48 位，每个槽位单独装进一个 Box，由 free_box 释放
+    macro_rules! kinds {
+        ($($kind:ident => $variant:ident($ty:ty), |$value:ident| $into:expr, |$raw:ident| $from:expr;)*) => {
+            impl Slot {
+                #[inline]
+                pub fn new(value: Object) -> Self {
+                    Slot(match value {
+                        Object::Number(n) if n.is_nan() => CANONICAL_NAN,
+                        Object::Number(n) => n.to_bits(),
+                        Object::NULL => pack(CONSTANT, NIL),
+                        Object::Boolean(false) => pack(CONSTANT, FALSE),
+                        Object::Boolean(true) => pack(CONSTANT, TRUE),
+                        Object::Uninitialized => pack(CONSTANT, UNINITIALIZED),
+                        $(Object::$variant($value) => {
+                            let raw: *const $ty = $into;
+                            pack($kind, raw as *const () as u64)
+                        })*
+                    })
+                }
+                // SAFETY: 返回的值和槽位共用同一份引用，两者只能有一个把它释放掉
+                #[inline]
+                unsafe fn take(&self) -> Object {
+                    match self.decode() {
+                        None => Object::Number(f64::from_bits(self.0)),
+                        Some((CONSTANT, NIL)) => Object::NULL,
+                        Some((CONSTANT, FALSE)) => Object::Boolean(false),
+                        Some((CONSTANT, TRUE)) => Object::Boolean(true),
+                        Some((CONSTANT, UNINITIALIZED)) => Object::Uninitialized,
+                        $(Some(($kind, address)) => {
+                            let $raw = address as *mut $ty;
+                            Object::$variant(unsafe { $from })
+                        })*
+                        Some((kind, payload)) => unreachable!("未知的值 {} {}", kind, payload),
+                    }
+                }
+            }
+        };
+    }
+    kinds! {
+        STRING => String(Interned), |s| Rc::into_raw(s.into_rc()), |raw| Symbol::from_rc(Rc::from_raw(raw));
+        CALLABLE => Callable(Rc<dyn LoxCallable>), |c| Box::into_raw(Box::new(c)), |raw| std::ptr::read(raw);
+        FUNCTION => Function(LoxFunction), |f| Rc::into_raw(f), |raw| Rc::from_raw(raw);
+        CLASS => Class(LoxClass), |c| Rc::into_raw(c), |raw| Rc::from_raw(raw);
+        INSTANCE => Instance(RefCell<LoxInstance>), |i| Rc::into_raw(i), |raw| Rc::from_raw(raw);
+        LIST => List(RefCell<Vec<super::Slot>>), |l| Rc::into_raw(l), |raw| Rc::from_raw(raw);
+        MAP => Map(RefCell<LoxMap>), |m| Rc::into_raw(m), |raw| Rc::from_raw(raw);
+        CLOSURE => Closure(Closure), |c| Rc::into_raw(c), |raw| Rc::from_raw(raw);
+        BOUND_METHOD => BoundMethod(BoundMethod), |b| Rc::into_raw(b), |raw| Rc::from_raw(raw);
+    }
+
+    impl Slot {
+        /// 取出槽位里的值的一份拷贝。
+        #[inline]
+        pub fn get(&self) -> Object {
+            self.with(Object::clone)
+        }
+        /// 把值从槽位里拿出来，不改变引用计数。
+        #[inline]
+        pub fn into_object(self) -> Object {
+            // SAFETY: 引用交给返回的值，self 不再执行 Drop
+            let value = unsafe { self.take() };
+            self.free_box();
+            std::mem::forget(self);
+            value
+        }
+        /// 借用槽位里的值，不改变引用计数。
+        #[inline]
+        pub fn with<R>(&self, f: impl FnOnce(&Object) -> R) -> R {
+            // SAFETY: 借用期间槽位一直持有这份引用；ManuallyDrop 保证不会多释放一次
+            let value = ManuallyDrop::new(unsafe { self.take() });
+            f(&value)
+        }
+    }
+    impl Clone for Slot {
+        // 数字和常量没有引用，直接复制位
+        #[inline]
+        fn clone(&self) -> Self {
+            match self.decode() {
+                None | Some((CONSTANT, _)) => Slot(self.0),
+                Some(_) => Slot::new(self.get()),
+            }
+        }
+    }
+    impl Drop for Slot {
+        // 交还槽位持有的引用；值里的容器交给 memory::release，列表套列表这样的长链不在这里递归释放
+        #[inline]
+        fn drop(&mut self) {
+            if self.decode().is_some_and(|(kind, _)| kind != CONSTANT) {
+                // SAFETY: 之后 self 不再使用这份引用
+                let value = unsafe { self.take() };
+                self.free_box();
+                if memory::frees_contents(&value) {
+                    memory::release([Garbage::Object(value)]);
+                }
+            }
+        }
+    }
+
+    impl Slot {
+        // 释放原生函数的 Box 本身；里面的引用已经由 take 读出去了
+        #[inline]
+        fn free_box(&self) {
+            if let Some((CALLABLE, address)) = self.decode() {
+                let raw = address as *mut ManuallyDrop<Rc<dyn LoxCallable>>;
+                // SAFETY: 地址来自 Slot::new 里的 Box::into_raw，每个槽位只释放一次
+                drop(unsafe { Box::from_raw(raw) });
+            }
+        }
+        // 数字返回 None，其它值返回类型编号和载荷
+        #[inline]
+        fn decode(&self) -> Option<(u64, u64)> {
+            if self.0 & QNAN != QNAN || self.0 == CANONICAL_NAN {
+                return None;
+            }
+            let kind = (self.0 >> 60) & 8 | (self.0 >> 48) & 7;
+            Some((kind, self.0 & PAYLOAD))
+        }
+    }
+
+    #[inline]
+    fn pack(kind: u64, payload: u64) -> u64 {
+        // 常见平台上用户态地址都不超过 48 位
+        assert!(payload <= PAYLOAD, "地址超出了 NaN-boxing 能表示的 48 位");
+        let sign = if kind & 8 != 0 { SIGN } else { 0 };
+        sign | QNAN | (kind & 7) << 48 | payload
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::Slot;
+    use crate::{
+        Backend, Lox, lox_callable::NativeFunction, lox_class::LoxClass, lox_instance::LoxInstance,
+        lox_map::LoxMap, symbol::Symbol, token::Object,
+    };
+
+    // 值背后那份数据的强引用数；常量和数字没有引用，返回 None
+    fn strong_count(value: &Object) -> Option<usize> {
+        Some(match value {
+            Object::String(s) => s.strong_count(),
+            Object::Callable(c) => Rc::strong_count(c),
+            Object::Function(f) => Rc::strong_count(f),
+            Object::Class(c) => Rc::strong_count(c),
+            Object::Instance(i) => Rc::strong_count(i),
+            Object::List(l) => Rc::strong_count(l),
+            Object::Map(m) => Rc::strong_count(m),
+            Object::Closure(c) => Rc::strong_count(c),
+            Object::BoundMethod(b) => Rc::strong_count(b),
+            Object::NULL | Object::Number(_) | Object::Boolean(_) | Object::Uninitialized => {
+                return None;
+            }
+        })
+    }
+
+    fn run(backend: Backend, source: &str) -> Object {
+        let mut lox = Lox::new();
+        lox.set_backend(backend);
+        lox.run_source(source).expect("脚本出错")
+    }
+
+    // 每一种值各一个，对象类型覆盖 kinds! 里的每一项
+    fn values() -> Vec<Object> {
+        let class = Rc::new(LoxClass::new("A".to_string(), None, Default::default()));
+        let instance = LoxInstance::new(class.clone());
+        vec![
+            Object::NULL,
+            Object::Boolean(false),
+            Object::Boolean(true),
+            Object::Uninitialized,
+            Object::Number(1.5),
+            Object::Number(f64::NAN),
+            Object::Number(f64::NEG_INFINITY),
+            Object::String(Symbol::from(format!("slot-{}", 1))),
+            Object::Callable(Rc::new(NativeFunction::new("f", 0, |_| Ok(Object::NULL)))),
+            run(Backend::TreeWalker, "fun f() {} f;"),
+            Object::Class(class),
+            Object::Instance(Rc::new(RefCell::new(instance))),
+            Object::List(Rc::new(RefCell::new(vec![Slot::new(Object::Number(1.0))]))),
+            Object::Map(Rc::new(RefCell::new(LoxMap::new()))),
+            run(Backend::Vm, "fun f() {} f;"),
+            run(Backend::Vm, "class A { m() {} } A().m;"),
+        ]
+    }
+
+    #[test]
+    fn covers_every_kind() {
+        let values = values();
+        let kinds: [fn(&Object) -> bool; 9] = [
+            |v| matches!(v, Object::String(_)),
+            |v| matches!(v, Object::Callable(_)),
+            |v| matches!(v, Object::Function(_)),
+            |v| matches!(v, Object::Class(_)),
+            |v| matches!(v, Object::Instance(_)),
+            |v| matches!(v, Object::List(_)),
+            |v| matches!(v, Object::Map(_)),
+            |v| matches!(v, Object::Closure(_)),
+            |v| matches!(v, Object::BoundMethod(_)),
+        ];
+        for (i, kind) in kinds.iter().enumerate() {
+            assert!(values.iter().any(kind), "缺少第 {} 种对象", i + 1);
+        }
+    }
+
+    #[test]
+    fn round_trips_values() {
+        for value in values() {
+            let slot = Slot::new(value.clone());
+            let expected = format!("{:?}", value);
+            assert_eq!(format!("{:?}", slot.get()), expected);
+            assert_eq!(slot.with(|v| format!("{:?}", v)), expected);
+            assert_eq!(format!("{:?}", slot.clone().into_object()), expected);
+        }
+    }
+
+    #[test]
+    fn counts_references() {
+        for value in values() {
+            let Some(before) = strong_count(&value) else {
+                continue;
+            };
+            let name = format!("{:?}", value);
+            let mut slot = Slot::new(value.clone());
+            assert_eq!(strong_count(&value), Some(before + 1), "new {}", name);
+            let copy = slot.clone();
+            assert_eq!(strong_count(&value), Some(before + 2), "clone {}", name);
+            let got = slot.get();
+            assert_eq!(strong_count(&value), Some(before + 3), "get {}", name);
+            drop(got);
+            slot.with(|_| ());
+            assert_eq!(strong_count(&value), Some(before + 2), "with {}", name);
+            slot = Slot::new(Object::Number(0.0));
+            assert_eq!(strong_count(&value), Some(before + 1), "overwrite {}", name);
+            let object = copy.into_object();
+            assert_eq!(
+                strong_count(&value),
+                Some(before + 1),
+                "into_object {}",
+                name
+            );
+            drop(object);
+            assert_eq!(strong_count(&value), Some(before), "drop {}", name);
+            drop(slot);
+            let slot = Slot::new(value.clone());
+            drop(slot);
+            assert_eq!(strong_count(&value), Some(before), "drop {}", name);
+        }
+    }
+}
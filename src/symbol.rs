@@ -0,0 +1,179 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    hash::{BuildHasherDefault, Hash, Hasher},
+    ops::Deref,
+    rc::{Rc, Weak},
+};
+
+/// 驻留（intern）过的字符串。内容相同的字符串在同一个线程里共享同一份数据，
+/// 所以比较和哈希只看地址，是一次整数运算，不用逐个字符比较。
+///
+/// 标识符、字符串字面量和运行时拼出来的字符串都用它表示。驻留表只保存弱引用，
+/// 字符串不再被使用时照样释放，循环里不断拼接字符串不会让驻留表越来越大。
+///
+/// ```
+/// use jlox::Symbol;
+///
+/// let a = Symbol::intern("fib");
+/// let b = Symbol::from(format!("f{}", "ib"));
+/// assert_eq!(a, b);
+/// assert_eq!(a.as_ptr(), b.as_ptr());
+/// assert_ne!(a, Symbol::intern("fi"));
+/// assert_eq!(a, "fib");
+/// ```
+#[derive(Clone)]
+pub struct Symbol(Rc<Interned>);
+
+pub(crate) struct Interned {
+    text: Box<str>,
+    hash: u64,
+}
+
+// 按内容的哈希值分桶；同一个桶里可能有内容不同但哈希相同的字符串
+#[derive(Default)]
+struct Table {
+    buckets: HashMap<u64, Vec<Weak<Interned>>, BuildHasherDefault<SymbolHasher>>,
+}
+
+thread_local! {
+    static TABLE: RefCell<Table> = RefCell::new(Table::default());
+}
+
+impl Symbol {
+    pub fn intern(text: &str) -> Symbol {
+        let hash = content_hash(text);
+        TABLE.with(|table| {
+            let mut table = table.borrow_mut();
+            let bucket = table.buckets.entry(hash).or_default();
+            for entry in bucket.iter() {
+                if let Some(interned) = entry.upgrade()
+                    && *interned.text == *text
+                {
+                    return Symbol(interned);
+                }
+            }
+            let interned = Rc::new(Interned {
+                text: text.into(),
+                hash,
+            });
+            bucket.push(Rc::downgrade(&interned));
+            Symbol(interned)
+        })
+    }
+    pub fn as_str(&self) -> &str {
+        &self.0.text
+    }
+    /// 字符串数据的地址，在字符串存活期间唯一标识它。
+    pub fn as_ptr(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+    // NaN-boxing 的槽位只存地址，用这两个方法交出和取回引用
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) fn into_rc(self) -> Rc<Interned> {
+        self.0
+    }
+    #[cfg(feature = "nan-boxing")]
+    pub(crate) fn from_rc(interned: Rc<Interned>) -> Symbol {
+        Symbol(interned)
+    }
+    #[cfg(test)]
+    pub(crate) fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+}
+impl Drop for Interned {
+    // 最后一个引用释放时把自己从驻留表里删掉；线程退出时驻留表可能已经销毁，这时什么都不用做
+    fn drop(&mut self) {
+        let _ = TABLE.try_with(|table| {
+            let Ok(mut table) = table.try_borrow_mut() else {
+                return;
+            };
+            if let Some(bucket) = table.buckets.get_mut(&self.hash) {
+                bucket.retain(|entry| entry.strong_count() > 0);
+                if bucket.is_empty() {
+                    table.buckets.remove(&self.hash);
+                }
+            }
+        });
+    }
+}
+impl Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for Symbol {}
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.as_ptr() as usize);
+    }
+}
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+impl From<&str> for Symbol {
+    fn from(text: &str) -> Self {
+        Symbol::intern(text)
+    }
+}
+impl From<String> for Symbol {
+    fn from(text: String) -> Self {
+        Symbol::intern(&text)
+    }
+}
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// 以 [`Symbol`] 为键的哈希表。键本身就是唯一的地址，用不着 SipHash 这样抗碰撞的哈希函数。
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<SymbolHasher>>;
+
+// 地址和已经算好的哈希值只做一次乘法再循环移位打散（地址的低几位总是 0）；其它输入按 FNV-1a 处理
+#[derive(Default)]
+pub struct SymbolHasher(u64);
+impl Hasher for SymbolHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0 ^ n)
+            .wrapping_mul(0x9e3779b97f4a7c15)
+            .rotate_left(26);
+    }
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+}
+
+fn content_hash(text: &str) -> u64 {
+    let mut hasher = SymbolHasher(0xcbf29ce484222325);
+    hasher.write(text.as_bytes());
+    hasher.finish()
+}
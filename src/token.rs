@@ -1,16 +1,41 @@
-use crate::token_type::TokenType;
-#[derive(Debug, Clone, PartialEq)]
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    lox_callable::LoxCallable,
+    lox_class::LoxClass,
+    lox_function::LoxFunction,
+    lox_instance::LoxInstance,
+    lox_map::LoxMap,
+    slot::Slot,
+    symbol::Symbol,
+    token_type::TokenType,
+    vm::{BoundMethod, Closure},
+};
+#[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Object {
-    String(String),
+    // 字符串都是驻留过的，比较时只看地址
+    String(Symbol),
     NULL,
     Number(f64),
     Boolean(bool),
     Uninitialized,
+    Function(Rc<LoxFunction>),
+    // 宿主程序注册的原生函数等其它可调用对象
+    Callable(Rc<dyn LoxCallable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    // 列表是可变的，并且按引用共享
+    List(Rc<RefCell<Vec<Slot>>>),
+    Map(Rc<RefCell<LoxMap>>),
+    // 字节码虚拟机的函数，以及绑定了 this 的方法
+    Closure(Rc<Closure>),
+    BoundMethod(Rc<BoundMethod>),
 }
 #[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub lexeme: String,
+    pub lexeme: Symbol,
     pub literal: Object,
     pub line: usize,
     pub column: usize,
@@ -25,7 +50,7 @@ impl Token {
     ) -> Self {
         Self {
             token_type,
-            lexeme,
+            lexeme: Symbol::from(lexeme),
             literal,
             line,
             column,
@@ -5,7 +5,10 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -26,16 +29,23 @@ pub enum TokenType {
     // Literals
     Identifier,
     String,
+    // 字符串中 ${ 之前的部分，后面跟着插值表达式的记号
+    Interpolation,
+    // 插值表达式结尾的 }，后面是字符串剩下的部分（String 或者下一个 Interpolation）
+    InterpolationEnd,
     Number,
 
     // Keywords
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -0,0 +1,860 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    chunk::{Constant, Function, OpCode},
+    interpreter::{Frame, Interpreter, RuntimeError, RuntimeErrorKind},
+    lox_class::LoxClass,
+    lox_instance::LoxInstance,
+    lox_map::{LoxMap, MapKey},
+    memory::{self, MAP_ENTRY_BYTES},
+    slot::Slot,
+    symbol::{Symbol, SymbolMap},
+    token::Object,
+};
+
+/// 虚拟机里的函数值：编译好的函数加上捕获的上值。
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // 定义函数的源码所在的文件，用于调用栈
+    pub file: Option<Rc<str>>,
+}
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.function.name)
+    }
+}
+
+/// 被闭包捕获的变量。变量还在栈上时指向它的槽位，离开作用域后把值搬到这里。
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Slot),
+}
+
+/// 绑定了 this 的方法。
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Object,
+    pub method: Rc<Closure>,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    // 下一条要执行的指令
+    ip: usize,
+    // 槽位 0 在栈上的位置
+    base: usize,
+    // 通过调用类进入的 init，调用栈里显示类名
+    class: Option<Rc<LoxClass>>,
+    // 这一帧所在的文件：函数定义处的文件，调用类时是调用方的文件
+    file: Option<Rc<str>>,
+    // 最近执行的一条有位置的语句，被中止时报告在这里
+    step: Option<usize>,
+}
+
+/// 执行 [`crate::compiler::Compiler`] 编译出的字节码。
+///
+/// 全局变量、原生函数、输出、兼容模式和执行限制都来自传入的 [`Interpreter`]，
+/// 所以两种执行方式看到的是同一个全局环境，行为也一致。
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Slot>,
+    frames: Vec<CallFrame>,
+    // 还指向栈上槽位的上值，按槽位从小到大排列
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+impl Vm {
+    pub fn new() -> Self {
+        Vm::default()
+    }
+    /// 执行编译好的脚本，返回脚本的值（最后一条表达式语句的值）。
+    pub fn run(
+        &mut self,
+        interpreter: &mut Interpreter,
+        script: Rc<Function>,
+    ) -> Result<Object, RuntimeError> {
+        interpreter.restart_limits();
+        let closure = Rc::new(Closure {
+            function: script,
+            upvalues: Vec::new(),
+            file: interpreter.file(),
+        });
+        self.push(Object::Closure(closure.clone()));
+        self.frames.push(CallFrame {
+            file: closure.file.clone(),
+            closure,
+            ip: 0,
+            base: 0,
+            class: None,
+            step: None,
+        });
+        let result = self.execute(interpreter);
+        // 出错时留在栈上的值和帧都不再需要
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+        result
+    }
+    /// 当前能访问到的所有值大致占用的字节数：全局变量、栈上的值和正在执行的函数。
+    pub fn memory_usage(&self, interpreter: &Interpreter) -> usize {
+        let closures: Vec<Slot> = self
+            .frames
+            .iter()
+            .map(|frame| Slot::new(Object::Closure(frame.closure.clone())))
+            .collect();
+        memory::reachable_bytes([&interpreter.globals], self.stack.iter().chain(&closures))
+    }
+
+    fn execute(&mut self, interpreter: &mut Interpreter) -> Result<Object, RuntimeError> {
+        loop {
+            let byte = self.read_byte();
+            let Some(op) = OpCode::from_byte(byte) else {
+                return Err(self.error(format!("Invalid instruction {}.", byte)));
+            };
+            match op {
+                OpCode::Constant => {
+                    let function = self.function();
+                    let value = match self.read_constant(&function) {
+                        Constant::Number(n) => Object::Number(*n),
+                        Constant::String(s) => Object::String(s.clone()),
+                        Constant::Function(_) => return Err(self.error("Invalid constant.")),
+                    };
+                    self.push(value);
+                }
+                OpCode::Nil => self.push(Object::NULL),
+                OpCode::True => self.push(Object::Boolean(true)),
+                OpCode::False => self.push(Object::Boolean(false)),
+                OpCode::Uninitialized => {
+                    // jlox 里没有初始值的变量就是 nil
+                    let value = if interpreter.compat() {
+                        Object::NULL
+                    } else {
+                        Object::Uninitialized
+                    };
+                    self.push(value);
+                }
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_u16() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_u16() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?;
+                    let value = interpreter.globals.borrow().values.get(name).map(Slot::get);
+                    match value {
+                        Some(Object::Uninitialized) => {
+                            return Err(self.error(format!(
+                                "Variable '{}' must be initialized before use.",
+                                name
+                            )));
+                        }
+                        Some(value) => self.push(value),
+                        None => {
+                            return Err(self.error(format!("Undefined variable '{}'.", name)));
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?.clone();
+                    let value = self.pop();
+                    interpreter.globals.borrow_mut().define(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?;
+                    let mut globals = interpreter.globals.borrow_mut();
+                    match globals.values.get_mut(name) {
+                        Some(slot) => *slot = self.peek(0).clone(),
+                        None => {
+                            return Err(self.error(format!("Undefined variable '{}'.", name)));
+                        }
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_u16() as usize;
+                    let value = match &*self.frame().closure.upvalues[index].borrow() {
+                        Upvalue::Open(slot) => self.stack.get(*slot).cloned(),
+                        Upvalue::Closed(value) => Some(value.clone()),
+                    };
+                    // 损坏的字节码可能没有关闭上值就弹出了它指向的槽位
+                    let Some(value) = value else {
+                        return Err(self.error("Invalid upvalue."));
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_u16() as usize;
+                    let value = self.peek(0).clone();
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => match self.stack.get_mut(*slot) {
+                            Some(slot) => *slot = value,
+                            None => return Err(self.error("Invalid upvalue.")),
+                        },
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::CheckInitialized => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?;
+                    if self
+                        .peek(0)
+                        .with(|value| matches!(value, Object::Uninitialized))
+                    {
+                        return Err(self.error(format!(
+                            "Variable '{}' must be initialized before use.",
+                            name
+                        )));
+                    }
+                }
+                OpCode::GetProperty => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?;
+                    let object = self.pop();
+                    let value = self.get_property(interpreter, object, name)?;
+                    self.push(value);
+                }
+                OpCode::CheckInstance => {
+                    self.read_u16();
+                    if !self
+                        .peek(0)
+                        .with(|value| matches!(value, Object::Instance(_)))
+                    {
+                        return Err(self.error("Only instances have fields."));
+                    }
+                }
+                OpCode::SetProperty => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?;
+                    let value = self.pop();
+                    let Object::Instance(instance) = self.pop() else {
+                        return Err(self.error("Only instances have fields."));
+                    };
+                    if !instance.borrow().fields.contains_key(name) {
+                        self.allocate(interpreter, name.len() + size_of::<Slot>())?;
+                    }
+                    instance
+                        .borrow_mut()
+                        .fields
+                        .insert(name.clone(), Slot::new(value.clone()));
+                    self.push(value);
+                }
+                OpCode::GetSuper => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?;
+                    let superclass = self.pop();
+                    let receiver = self.pop();
+                    let method = match &superclass {
+                        Object::Class(class) => class.find_method(name),
+                        _ => None,
+                    };
+                    match method {
+                        Some(Object::Closure(method)) => {
+                            let bound = BoundMethod { receiver, method };
+                            self.push(Object::BoundMethod(Rc::new(bound)));
+                        }
+                        _ => return Err(self.error(format!("Undefined property '{}'.", name))),
+                    }
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Object::Boolean(Interpreter::is_equal(&a, &b)));
+                }
+                OpCode::NotEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Object::Boolean(!Interpreter::is_equal(&a, &b)));
+                }
+                OpCode::Greater => self.compare(|a, b| a > b)?,
+                OpCode::GreaterEqual => self.compare(|a, b| a >= b)?,
+                OpCode::Less => self.compare(|a, b| a < b)?,
+                OpCode::LessEqual => self.compare(|a, b| a <= b)?,
+                OpCode::Add => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let value = match (a, b) {
+                        (Object::Number(a), Object::Number(b)) => Object::Number(a + b),
+                        (Object::String(a), Object::String(b)) => {
+                            self.allocate(interpreter, a.len() + b.len())?;
+                            Object::String(Symbol::from(format!("{}{}", a, b)))
+                        }
+                        _ => {
+                            return Err(self.error("Operands must be two numbers or two strings."));
+                        }
+                    };
+                    self.push(value);
+                }
+                OpCode::Subtract => self.arithmetic(|a, b| a - b)?,
+                OpCode::Multiply => self.arithmetic(|a, b| a * b)?,
+                OpCode::Divide => self.arithmetic(|a, b| a / b)?,
+                OpCode::In => {
+                    let map = self.pop();
+                    let key = self.pop();
+                    let Object::Map(map) = map else {
+                        return Err(self.error("Right operand of 'in' must be a map."));
+                    };
+                    let key = MapKey::new(key).map_err(|message| self.error(message))?;
+                    let contains = map.borrow().contains(&key);
+                    self.push(Object::Boolean(contains));
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Object::Boolean(!Interpreter::is_truthy(&value)));
+                }
+                OpCode::Negate => match self.pop() {
+                    Object::Number(n) => self.push(Object::Number(-n)),
+                    _ => return Err(self.error("Operand must be a number.")),
+                },
+                OpCode::Print => {
+                    let value = self.pop();
+                    let frame = self.frame();
+                    let (line, column) = frame.closure.function.chunk.position(frame.ip - 1);
+                    let memory_usage = |interpreter: &Interpreter| self.memory_usage(interpreter);
+                    if let Err(e) = interpreter.print_with(&value, line, column, memory_usage) {
+                        return Err(self.complete(e));
+                    }
+                }
+                OpCode::Jump => {
+                    let distance = self.read_u16() as usize;
+                    self.frame_mut().ip += distance;
+                }
+                OpCode::JumpIfFalse => {
+                    let distance = self.read_u16() as usize;
+                    if !self.peek(0).with(Interpreter::is_truthy) {
+                        self.frame_mut().ip += distance;
+                    }
+                }
+                OpCode::Loop => {
+                    let distance = self.read_u16() as usize;
+                    self.frame_mut().ip -= distance;
+                }
+                OpCode::Call => {
+                    let count = self.read_byte() as usize;
+                    self.call_value(interpreter, count)?;
+                }
+                OpCode::Closure => {
+                    let enclosing = self.function();
+                    let Constant::Function(function) = self.read_constant(&enclosing) else {
+                        return Err(self.error("Invalid constant."));
+                    };
+                    let function = function.clone();
+                    self.allocate(interpreter, size_of::<Closure>())?;
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_u16() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(interpreter, self.frame().base + index)
+                        } else {
+                            self.frame().closure.upvalues[index].clone()
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    let closure = Closure {
+                        function,
+                        upvalues,
+                        file: self.frame().file.clone(),
+                    };
+                    self.push(Object::Closure(Rc::new(closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("没有正在执行的函数");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.push(result);
+                }
+                OpCode::Class => {
+                    let function = self.function();
+                    let name = self.read_string(&function)?.clone();
+                    let count = self.read_u16() as usize;
+                    let inherits = self.read_byte() == 1;
+                    let mut methods = SymbolMap::default();
+                    for method in self.stack.split_off(self.stack.len() - count) {
+                        let method = method.into_object();
+                        if let Object::Closure(closure) = &method {
+                            methods.insert(Symbol::from(closure.function.name.as_str()), method);
+                        }
+                    }
+                    // 有父类时，父类就在方法下面，是 super 所在的槽位
+                    let superclass = self.peek(0).with(|value| match value {
+                        Object::Class(superclass) if inherits => Some(superclass.clone()),
+                        _ => None,
+                    });
+                    let class = LoxClass::new(name.to_string(), superclass, methods);
+                    self.push(Object::Class(Rc::new(class)));
+                }
+                OpCode::CheckSuperclass => {
+                    if !self.peek(0).with(|value| matches!(value, Object::Class(_))) {
+                        return Err(self.error("Superclass must be a class."));
+                    }
+                }
+                OpCode::List => {
+                    let count = self.read_u16() as usize;
+                    let start = self.stack.len() - count;
+                    let list: Vec<Slot> = self.stack.drain(start..).collect();
+                    self.allocate(interpreter, list.len() * size_of::<Slot>())?;
+                    let list = interpreter.track(Object::List(Rc::new(RefCell::new(list))));
+                    self.push(list);
+                }
+                OpCode::Map => {
+                    let count = self.read_u16() as usize;
+                    let values = self.stack.split_off(self.stack.len() - count * 2);
+                    let mut map = LoxMap::new();
+                    let mut values = values.into_iter();
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        // 键已经由 CheckKey 检查过
+                        let key = MapKey::new(key.into_object())
+                            .map_err(|message| self.error(message))?;
+                        map.set(key, value.into_object());
+                    }
+                    self.allocate(interpreter, map.entries().len() * MAP_ENTRY_BYTES)?;
+                    let map = interpreter.track(Object::Map(Rc::new(RefCell::new(map))));
+                    self.push(map);
+                }
+                OpCode::CheckKey => {
+                    if let Err(message) = MapKey::new(self.peek(0).get()) {
+                        return Err(self.error(message));
+                    }
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = match object {
+                        Object::List(list) => {
+                            let list = list.borrow();
+                            let i = Interpreter::list_index(&index, list.len())
+                                .map_err(|message| self.error(message))?;
+                            list[i].get()
+                        }
+                        Object::Map(map) => {
+                            let key = MapKey::new(index).map_err(|message| self.error(message))?;
+                            let value = map.borrow().get(&key);
+                            match value {
+                                Some(value) => value,
+                                None => {
+                                    let key = interpreter
+                                        .stringify_with(key.object(), |interpreter| {
+                                            self.memory_usage(interpreter)
+                                        })
+                                        .map_err(|e| self.complete(e))?;
+                                    return Err(self.error(format!("Undefined key '{}'.", key)));
+                                }
+                            }
+                        }
+                        _ => return Err(self.error("Only lists and maps can be indexed.")),
+                    };
+                    self.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    match object {
+                        Object::List(list) => {
+                            let mut list = list.borrow_mut();
+                            let i = Interpreter::list_index(&index, list.len())
+                                .map_err(|message| self.error(message))?;
+                            list[i] = Slot::new(value.clone());
+                        }
+                        Object::Map(map) => {
+                            let key = MapKey::new(index).map_err(|message| self.error(message))?;
+                            if !map.borrow().contains(&key) {
+                                self.allocate(interpreter, MAP_ENTRY_BYTES)?;
+                            }
+                            map.borrow_mut().set(key, value.clone());
+                        }
+                        _ => return Err(self.error("Only lists and maps can be indexed.")),
+                    }
+                    self.push(value);
+                }
+                OpCode::Stringify => {
+                    if !self
+                        .peek(0)
+                        .with(|value| matches!(value, Object::String(_)))
+                    {
+                        let value = self.pop();
+                        let text = interpreter
+                            .stringify_with(&value, |interpreter| self.memory_usage(interpreter))
+                            .map_err(|e| self.complete(e))?;
+                        self.push(Object::String(Symbol::from(text)));
+                    }
+                }
+                OpCode::Interpolate => {
+                    let count = self.read_u16() as usize;
+                    let mut result = String::new();
+                    for part in self.stack.split_off(self.stack.len() - count) {
+                        part.with(|part| {
+                            if let Object::String(part) = part {
+                                result.push_str(part);
+                            }
+                        });
+                    }
+                    self.allocate(interpreter, result.len())?;
+                    self.push(Object::String(Symbol::from(result)));
+                }
+                OpCode::Step => {
+                    if let Err(e) = interpreter.step() {
+                        return Err(self.complete(e));
+                    }
+                }
+                OpCode::StepAt => {
+                    let frame = self.frame_mut();
+                    frame.step = Some(frame.ip - 1);
+                    if let Err(e) = interpreter.step() {
+                        return Err(self.complete(e));
+                    }
+                }
+            }
+        }
+    }
+
+    fn compare(&mut self, compare: fn(f64, f64) -> bool) -> Result<(), RuntimeError> {
+        match (self.pop(), self.pop()) {
+            (Object::Number(b), Object::Number(a)) => {
+                self.push(Object::Boolean(compare(a, b)));
+                Ok(())
+            }
+            _ => Err(self.error("Operands must be numbers.")),
+        }
+    }
+    fn arithmetic(&mut self, operate: fn(f64, f64) -> f64) -> Result<(), RuntimeError> {
+        match (self.pop(), self.pop()) {
+            (Object::Number(b), Object::Number(a)) => {
+                self.push(Object::Number(operate(a, b)));
+                Ok(())
+            }
+            _ => Err(self.error("Operands must be numbers.")),
+        }
+    }
+    fn get_property(
+        &mut self,
+        interpreter: &mut Interpreter,
+        object: Object,
+        name: &Symbol,
+    ) -> Result<Object, RuntimeError> {
+        match &object {
+            Object::Instance(instance) => {
+                // 字段优先于方法
+                if let Some(value) = instance.borrow().fields.get(name) {
+                    return Ok(value.get());
+                }
+                let method = instance.borrow().class.find_method(name);
+                match method {
+                    Some(Object::Closure(method)) => {
+                        let bound = BoundMethod {
+                            receiver: object.clone(),
+                            method,
+                        };
+                        Ok(Object::BoundMethod(Rc::new(bound)))
+                    }
+                    _ => Err(self.error(format!("Undefined property '{}'.", name))),
+                }
+            }
+            Object::List(list) if name == "length" => {
+                Ok(Object::Number(list.borrow().len() as f64))
+            }
+            Object::Map(map) if name == "length" => {
+                Ok(Object::Number(map.borrow().entries().len() as f64))
+            }
+            Object::Map(map) if name == "keys" => {
+                self.allocate(
+                    interpreter,
+                    map.borrow().entries().len() * size_of::<Slot>(),
+                )?;
+                let keys = map.borrow().keys();
+                Ok(interpreter.track(Object::List(Rc::new(RefCell::new(keys)))))
+            }
+            _ => Err(self.error("Only instances have properties.")),
+        }
+    }
+
+    // 被调用的值在参数下面
+    fn call_value(
+        &mut self,
+        interpreter: &mut Interpreter,
+        count: usize,
+    ) -> Result<(), RuntimeError> {
+        let base = self.stack.len() - count - 1;
+        let callee = self.stack[base].get();
+        let arity = match &callee {
+            Object::Closure(closure) => closure.function.arity,
+            Object::BoundMethod(bound) => bound.method.function.arity,
+            Object::Callable(callable) => callable.arity(),
+            Object::Class(class) => class.arity(),
+            _ => return Err(self.error("Can only call functions and classes.")),
+        };
+        if count != arity {
+            return Err(self.error(format!("Expected {} arguments but got {}.", arity, count)));
+        }
+        match callee {
+            Object::Closure(closure) => {
+                self.push_frame(interpreter, closure, base, None)?;
+            }
+            Object::BoundMethod(bound) => {
+                self.stack[base] = Slot::new(bound.receiver.clone());
+                self.push_frame(interpreter, bound.method.clone(), base, None)?;
+            }
+            Object::Callable(callable) => {
+                let arguments = self.stack.split_off(base + 1);
+                let arguments = arguments.into_iter().map(Slot::into_object).collect();
+                self.stack.pop();
+                match callable.call(interpreter, arguments) {
+                    Ok(value) => self.push(value),
+                    Err(e) => {
+                        return Err(self.call_error(e, Frame::new(callable.name(), None)));
+                    }
+                }
+            }
+            Object::Class(class) => {
+                if let Err(e) = self.allocate(interpreter, size_of::<LoxInstance>()) {
+                    let frame = Frame::new(class.name.clone(), self.frame().file.clone());
+                    return Err(self.call_error(e, frame));
+                }
+                let instance = Rc::new(RefCell::new(LoxInstance::new(class.clone())));
+                self.stack[base] = Slot::new(interpreter.track(Object::Instance(instance)));
+                // 没有 init 时参数个数一定是 0，实例就是结果
+                if let Some(Object::Closure(initializer)) =
+                    class.find_method(&Symbol::intern("init"))
+                {
+                    self.push_frame(interpreter, initializer, base, Some(class))?;
+                }
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+    fn push_frame(
+        &mut self,
+        interpreter: &Interpreter,
+        closure: Rc<Closure>,
+        base: usize,
+        class: Option<Rc<LoxClass>>,
+    ) -> Result<(), RuntimeError> {
+        // 无限递归时报错，而不是一直占用内存；上限和树遍历解释器相同
+        if self.frames.len() >= interpreter.limits().max_call_depth {
+            return Err(self.error("Stack overflow."));
+        }
+        // 调用类时和树遍历解释器一样，用调用方的文件
+        let file = match &class {
+            Some(_) => self.frame().file.clone(),
+            None => closure.file.clone(),
+        };
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base,
+            class,
+            file,
+            step: None,
+        });
+        Ok(())
+    }
+    fn capture_upvalue(
+        &mut self,
+        interpreter: &mut Interpreter,
+        slot: usize,
+    ) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .partition_point(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s < slot));
+        if let Some(existing) = self.open_upvalues.get(position)
+            && matches!(*existing.borrow(), Upvalue::Open(s) if s == slot)
+        {
+            return existing.clone();
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        interpreter.heap().track_upvalue(&upvalue);
+        self.open_upvalues.insert(position, upvalue.clone());
+        upvalue
+    }
+    // 关闭槽位不小于 from 的上值：把栈上的值搬进上值里
+    fn close_upvalues(&mut self, from: usize) {
+        while let Some(upvalue) = self
+            .open_upvalues
+            .pop_if(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(slot) if slot >= from))
+        {
+            let mut upvalue = upvalue.borrow_mut();
+            if let Upvalue::Open(slot) = *upvalue {
+                // 槽位已经不在栈上（只有损坏的字节码会这样）时上值就是 nil
+                let value = self.stack.get(slot).cloned();
+                *upvalue = Upvalue::Closed(value.unwrap_or_else(|| Slot::new(Object::NULL)));
+            }
+        }
+    }
+    fn allocate(&self, interpreter: &mut Interpreter, bytes: usize) -> Result<(), RuntimeError> {
+        interpreter
+            .allocate_with(bytes, |interpreter| self.memory_usage(interpreter))
+            .map_err(|e| self.complete(e))
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("没有正在执行的函数")
+    }
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("没有正在执行的函数")
+    }
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame_mut();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+    // 常量从传入的函数里取，不占用 self 的借用
+    fn function(&self) -> Rc<Function> {
+        self.frame().closure.function.clone()
+    }
+    fn read_constant<'a>(&mut self, function: &'a Function) -> &'a Constant {
+        let index = self.read_u16() as usize;
+        &function.chunk.constants[index]
+    }
+    fn read_string<'a>(&mut self, function: &'a Function) -> Result<&'a Symbol, RuntimeError> {
+        match self.read_constant(function) {
+            Constant::String(s) => Ok(s),
+            _ => Err(self.error("Invalid constant.")),
+        }
+    }
+    fn push(&mut self, value: Object) {
+        self.stack.push(Slot::new(value));
+    }
+    fn pop(&mut self) -> Object {
+        self.stack.pop().expect("栈是空的").into_object()
+    }
+    fn peek(&self, distance: usize) -> &Slot {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    // 当前指令出错：位置就是指令的位置
+    fn error(&self, message: impl Into<String>) -> RuntimeError {
+        let frame = self.frame();
+        let (line, column) = frame.closure.function.chunk.position(frame.ip - 1);
+        RuntimeError {
+            message: message.into(),
+            line,
+            column,
+            kind: RuntimeErrorKind::Error,
+            trace: self.trace((line, column)),
+        }
+    }
+    // 解释器报告的不知道位置的错误（执行限制、输出失败）：和树遍历解释器一样，
+    // 算在最近执行的那条语句上
+    fn complete(&self, mut e: RuntimeError) -> RuntimeError {
+        if !e.trace.is_empty() {
+            return e;
+        }
+        let (line, column) = self.step_position();
+        if e.kind != RuntimeErrorKind::Error {
+            e.line = line;
+            e.column = column;
+        }
+        e.trace = self.trace((line, column));
+        e
+    }
+    // 调用原生函数或者创建实例时出错：调用栈里多出被调用的那一帧，没有位置的错误算在调用处
+    fn call_error(&self, mut e: RuntimeError, mut callee: Frame) -> RuntimeError {
+        if !e.trace.is_empty() {
+            return e;
+        }
+        let frame = self.frame();
+        let (line, column) = frame.closure.function.chunk.position(frame.ip - 1);
+        e.trace = self.trace((line, column));
+        if e.line != 0 && e.kind == RuntimeErrorKind::Error {
+            callee.line = e.line;
+            callee.column = e.column;
+        }
+        e.trace.push(callee);
+        if e.line == 0 || e.kind != RuntimeErrorKind::Error {
+            e.line = line;
+            e.column = column;
+        }
+        e
+    }
+    // 最内层的块里最近执行的一条有位置的语句；中间隔着已经执行完的块时，停在那个块语句上
+    fn step_position(&self) -> (usize, usize) {
+        let frame = self.frame();
+        let chunk = &frame.closure.function.chunk;
+        let offset = frame.ip - 1;
+        let Some(step) = frame.step.filter(|&step| step <= offset) else {
+            return (0, 0);
+        };
+        let start = chunk
+            .blocks
+            .iter()
+            .filter(|b| b.start <= offset && offset < b.end)
+            .map(|b| b.start)
+            .max()
+            .unwrap_or(0);
+        if step < start {
+            return (0, 0);
+        }
+        let finished = chunk
+            .blocks
+            .iter()
+            .filter(|b| b.start >= start && b.start <= step && step < b.end && offset >= b.end)
+            .min_by_key(|b| b.start);
+        match finished {
+            Some(block) => (block.line, block.column),
+            None => chunk.position(step),
+        }
+    }
+    // 按树遍历解释器的样子给出调用栈：每一帧之后是它里面正在执行的块，
+    // 外层停在调用处或者内层块语句的位置，最内层停在 at
+    fn trace(&self, at: (usize, usize)) -> Vec<Frame> {
+        let mut trace = Vec::new();
+        let last = self.frames.len() - 1;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let chunk = &frame.closure.function.chunk;
+            let offset = frame.ip - 1;
+            let name = match &frame.class {
+                Some(class) => class.name.clone(),
+                None => frame.closure.function.name.clone(),
+            };
+            let mut levels = vec![Frame::new(name, frame.file.clone())];
+            for block in chunk
+                .blocks
+                .iter()
+                .filter(|b| b.start <= offset && offset < b.end)
+            {
+                if let Some(outer) = levels.last_mut() {
+                    outer.line = block.line;
+                    outer.column = block.column;
+                }
+                levels.push(Frame::new("<block>", frame.file.clone()));
+            }
+            let (line, column) = if i == last {
+                at
+            } else {
+                chunk.position(offset)
+            };
+            if let Some(innermost) = levels.last_mut() {
+                innermost.line = line;
+                innermost.column = column;
+            }
+            trace.extend(levels);
+        }
+        trace
+    }
+}
@@ -1,7 +1,27 @@
 #!/bin/bash
+# 用 craftinginterpreters 仓库里的官方测试集检查兼容模式（--compat）的输出和退出码。
+# 需要把 craftinginterpreters 克隆到本仓库旁边，并安装 dart。
+#
+# chap04_scanning、chap06_parsing 和 chap07_evaluating 测的是书中间阶段的程序
+# （打印记号、打印语法树、直接对单个表达式求值），完整的解释器没有这些输出；
+# 从 chap08 开始的每一章以及完整的 jlox 测试集都应该通过。
 
 set -euo pipefail
 
 cargo build
 cd ../craftinginterpreters
-dart ./tool/bin/test.dart jlox-rust chap07_evaluating --interpreter ../jlox-rust/target/debug/jlox-rust
\ No newline at end of file
+
+suites=(
+    chap08_statements
+    chap09_control
+    chap10_functions
+    chap11_resolving
+    chap12_classes
+    chap13_inheritance
+    jlox
+)
+for suite in "${suites[@]}"; do
+    dart ./tool/bin/test.dart "$suite" \
+        --interpreter ../jlox-rust/target/debug/jlox-rust \
+        --arguments --compat
+done
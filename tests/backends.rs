@@ -0,0 +1,29 @@
+//! 同一段程序交给树遍历解释器和字节码虚拟机执行，程序输出、诊断信息和返回的错误都必须一样。
+mod common;
+
+use common::output;
+
+#[test]
+fn super_calls() {
+    let source = r#"
+        class A {
+          init(name) { this.name = name; }
+          say() { return "A " + this.name; }
+        }
+        class B < A {
+          init(name) { super.init(name + "!"); }
+          say() { return "B " + super.say(); }
+        }
+        class C < B {
+          say() {
+            fun later() { return "C " + super.say(); }
+            return later;
+          }
+        }
+        print C("c").say()();
+        var method = B("b").say;
+        print method();
+        print C("x").name;
+    "#;
+    assert_eq!(output(source), "C B A c!\nB A b!\nx!\n");
+}
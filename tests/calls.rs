@@ -0,0 +1,47 @@
+//! 函数调用的深度上限：两种执行方式都报 Stack overflow.，而不是耗尽原生栈。
+mod common;
+
+use common::{both, both_on};
+use jlox::interpreter::Limits;
+
+#[test]
+fn stack_overflow() {
+    let source = r#"
+        fun depth(n) { if (n == 0) return 0; return 1 + depth(n - 1); }
+        print depth(5000);
+        fun forever(n) { return forever(n + 1); }
+        forever(0);
+    "#;
+    let limits = Limits {
+        max_call_depth: 10_000,
+        ..Limits::default()
+    };
+    let run = both(limits, source);
+    assert_eq!(run.output, "5000\n");
+    assert!(
+        run.diagnostics.contains("Stack overflow."),
+        "{}",
+        run.diagnostics
+    );
+}
+
+#[test]
+fn stack_overflow_on_ordinary_stack() {
+    // 默认的调用深度上限在普通大小的栈上先报错，不会耗尽原生栈
+    let source = r#"
+        fun depth(n) {
+          if (n == 0) return 0;
+          { var m = n - 1; return 1 + depth(m); }
+        }
+        print depth(490);
+        fun forever(n) { return forever(n + 1); }
+        forever(0);
+    "#;
+    let run = both_on(8 << 20, Limits::default(), source);
+    assert_eq!(run.output, "490\n");
+    assert!(
+        run.diagnostics.contains("Stack overflow."),
+        "{}",
+        run.diagnostics
+    );
+}
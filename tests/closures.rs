@@ -0,0 +1,41 @@
+//! 闭包：捕获定义时的环境，两种执行方式结果相同。
+mod common;
+
+use common::output;
+
+#[test]
+fn closures() {
+    let source = r#"
+        fun counter() {
+          var n = 0;
+          fun add() { n = n + 1; return n; }
+          return add;
+        }
+        var a = counter();
+        var b = counter();
+        print a(); print a(); print b();
+
+        // 两个闭包共享同一个变量
+        var get; var set;
+        {
+          var shared = "before";
+          fun g() { return shared; }
+          fun s(v) { shared = v; }
+          get = g; set = s;
+        }
+        set("after");
+        print get();
+
+        // 闭包套闭包，捕获的是外层闭包捕获的变量
+        fun outer() {
+          var x = "outer";
+          fun middle() {
+            fun inner() { return x; }
+            return inner;
+          }
+          return middle;
+        }
+        print outer()()();
+    "#;
+    assert_eq!(output(source), "1\n2\n1\nafter\nouter\n");
+}
@@ -0,0 +1,64 @@
+//! 集成测试共用的辅助函数：同一段程序交给两种执行方式各跑一遍，比较结果。
+// 每个测试文件只用到其中一部分
+#![allow(dead_code)]
+
+use std::{cell::RefCell, rc::Rc, thread};
+
+use jlox::{Backend, Lox, interpreter::Limits};
+
+#[derive(Debug, PartialEq)]
+pub struct Run {
+    pub output: String,
+    pub diagnostics: String,
+    // 错误的 Debug 形式，连同调用栈一起比较；LoxError 里有 Rc，不能直接带出线程
+    pub errors: Vec<String>,
+}
+
+pub fn run(backend: Backend, limits: Limits, source: &str) -> Run {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let diagnostics = Rc::new(RefCell::new(Vec::new()));
+    let mut lox = Lox::new_with_sinks(output.clone(), diagnostics.clone());
+    lox.set_backend(backend);
+    lox.interpreter().set_limits(limits);
+    let errors = lox.run_named("test.lox", source).err().unwrap_or_default();
+    let errors = errors.iter().map(|e| format!("{:?}", e)).collect();
+    Run {
+        output: String::from_utf8(output.take()).unwrap(),
+        diagnostics: String::from_utf8(diagnostics.take()).unwrap(),
+        errors,
+    }
+}
+
+/// 在足够大的栈上用两种方式各跑一遍，结果相同时返回它。
+pub fn both(limits: Limits, source: &'static str) -> Run {
+    both_on(jlox::STACK_SIZE, limits, source)
+}
+
+/// 和 [`both`] 一样，在栈大小为 stack_size 的线程上运行。
+pub fn both_on(stack_size: usize, limits: Limits, source: &'static str) -> Run {
+    thread::Builder::new()
+        .stack_size(stack_size)
+        .spawn(move || {
+            let tree_walker = run(Backend::TreeWalker, limits, source);
+            let vm = run(Backend::Vm, limits, source);
+            assert_eq!(tree_walker, vm, "两种执行方式的结果不同：\n{}", source);
+            tree_walker
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// 不设限制运行，不能出错，返回程序输出。
+pub fn output(source: &'static str) -> String {
+    let run = both(Limits::default(), source);
+    assert!(run.errors.is_empty(), "{}", run.diagnostics);
+    run.output
+}
+
+/// 运行必须出错，返回诊断信息。
+pub fn error(limits: Limits, source: &'static str) -> String {
+    let run = both(limits, source);
+    assert!(!run.errors.is_empty(), "没有出错：\n{}", source);
+    run.diagnostics
+}
@@ -0,0 +1,59 @@
+//! 兼容模式（`--compat`）只认书中 jlox 的语法。
+use std::{cell::RefCell, rc::Rc};
+
+use jlox::{Backend, Lox};
+
+// 两种执行方式各跑一遍，结果相同时返回 (输出, 诊断信息)
+fn compat(source: &str) -> (String, String) {
+    let run = |backend| {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let diagnostics = Rc::new(RefCell::new(Vec::new()));
+        let mut lox = Lox::new_with_sinks(output.clone(), diagnostics.clone());
+        lox.set_compat(true);
+        lox.set_backend(backend);
+        let _ = lox.run_named("test.lox", source);
+        (
+            String::from_utf8(output.take()).unwrap(),
+            String::from_utf8(diagnostics.take()).unwrap(),
+        )
+    };
+    let tree_walker = run(Backend::TreeWalker);
+    assert_eq!(tree_walker, run(Backend::Vm), "{}", source);
+    tree_walker
+}
+
+#[test]
+fn extension_keywords_are_identifiers() {
+    let source = "var break = 1; var continue = 2; var in = 3;\nprint break + continue + in;\n";
+    assert_eq!(compat(source), ("6\n".to_string(), String::new()));
+}
+
+#[test]
+fn strings_have_no_escapes_or_interpolation() {
+    let source = r#"var a = 1; print "a\n${a}";"#;
+    assert_eq!(compat(source), ("a\\n${a}\n".to_string(), String::new()));
+}
+
+#[test]
+fn braces_in_expressions_are_errors() {
+    // craftinginterpreters 的 for/statement_initializer.lox 和 for/statement_condition.lox
+    for source in [
+        "for ({}; a < 2; a = a + 1) {}\n",
+        "for (var a = 0; {}; a = a + 1) {}\n",
+    ] {
+        let expected = "[line 1] Error at '{': Expect expression.\n\
+                        [line 1] Error at ')': Expect ';' after expression.\n";
+        assert_eq!(compat(source), (String::new(), expected.to_string()));
+    }
+}
+
+#[test]
+fn brackets_and_colons_are_unexpected() {
+    let unexpected = "[line 1] Error: Unexpected character.\n";
+    assert_eq!(compat("print [1];"), (String::new(), unexpected.repeat(2)));
+    let expected = format!(
+        "{}[line 1] Error at 'print': Expect ';' after expression.\n",
+        unexpected
+    );
+    assert_eq!(compat("a: print 1;"), (String::new(), expected));
+}
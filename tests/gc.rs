@@ -0,0 +1,22 @@
+//! 释放值：很长的引用链逐个释放，不能耗尽原生栈。
+mod common;
+
+use common::output;
+
+#[test]
+fn long_chains() {
+    // 很长的链表释放时不能耗尽原生栈
+    let source = r#"
+        class Node { init(next) { this.next = next; } }
+        var head = nil;
+        var list = nil;
+        for (var i = 0; i < 100000; i = i + 1) {
+          head = Node(head);
+          list = [list];
+        }
+        head = nil;
+        list = nil;
+        print "done";
+    "#;
+    assert_eq!(output(source), "done\n");
+}
@@ -0,0 +1,102 @@
+//! 执行限制：两种执行方式超出限制时都要报错，而且报在同一个位置。
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{both, error};
+use jlox::interpreter::Limits;
+
+#[test]
+fn step_limit() {
+    let limits = Limits {
+        max_steps: Some(1000),
+        ..Limits::default()
+    };
+    let diagnostics = error(limits, "var i = 0;\nwhile (true) {\n  i = i + 1;\n}\n");
+    assert!(diagnostics.contains("test.lox:3:"), "{}", diagnostics);
+}
+
+#[test]
+fn time_limit_while_printing() {
+    // 互相引用的列表展开后有上亿个元素，只执行了几十条语句，打印时也要受时间限制
+    let limits = Limits {
+        time_limit: Some(Duration::from_millis(50)),
+        ..Limits::default()
+    };
+    let source = r#"
+        var l = [];
+        for (var i = 0; i < 30; i = i + 1) l = [l, l];
+        print l;
+    "#;
+    let start = Instant::now();
+    let diagnostics = error(limits, source);
+    assert!(
+        diagnostics.contains("Execution time limit of 50ms exceeded.\n  --> test.lox:4:"),
+        "{}",
+        diagnostics
+    );
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn memory_limit() {
+    let limits = Limits {
+        max_memory: Some(1 << 20),
+        ..Limits::default()
+    };
+    let source = r#"
+        fun grow() {
+          var s = "x";
+          while (true) s = s + s;
+        }
+        grow();
+    "#;
+    let diagnostics = error(limits, source);
+    assert!(
+        diagnostics.contains("at grow (test.lox:4:"),
+        "{}",
+        diagnostics
+    );
+}
+
+#[test]
+fn memory_limit_while_printing() {
+    // 共享元素的列表只占几 KB，展开成字符串却有几百 MB，要在拼的过程中就停下
+    let limits = Limits {
+        max_memory: Some(1 << 20),
+        ..Limits::default()
+    };
+    for source in [
+        "var l = [];\nfor (var i = 0; i < 24; i = i + 1) l = [l, l];\nprint l;\n",
+        "var l = [];\nfor (var i = 0; i < 24; i = i + 1) l = [l, l];\nvar s = \"${l}\";\n",
+        "fun f() {\n  var l = [];\n  for (var i = 0; i < 24; i = i + 1) l = [l, l];\n  return \"${l}\";\n}\nf();\n",
+    ] {
+        let diagnostics = error(limits, source);
+        assert!(
+            diagnostics.contains("Memory limit of 1048576 bytes exceeded."),
+            "{}",
+            diagnostics
+        );
+    }
+}
+
+#[test]
+fn shared_strings_counted_once() {
+    // 一千个列表引用同一个 64 KB 的字符串，字符串只占一份内存
+    let limits = Limits {
+        max_memory: Some(1 << 20),
+        ..Limits::default()
+    };
+    let source = r#"
+        var s = "x";
+        for (var i = 0; i < 16; i = i + 1) s = s + s;
+        var l = [];
+        for (var i = 0; i < 1000; i = i + 1) l = [l, s];
+        // 分配的量累计够了才会重新统计占用
+        for (var i = 0; i < 50000; i = i + 1) { var garbage = [i, i, i]; }
+        print l.length;
+    "#;
+    let run = both(limits, source);
+    assert!(run.errors.is_empty(), "{}", run.diagnostics);
+    assert_eq!(run.output, "2\n");
+}
@@ -0,0 +1,34 @@
+//! 循环里的 break 和 continue：跳出循环时关闭循环体里被捕获的变量。
+mod common;
+
+use common::output;
+
+#[test]
+fn upvalues_closed_on_break_and_continue() {
+    let source = r#"
+        var fs = [nil, nil, nil, nil, nil];
+        for (var i = 0; i < 5; i = i + 1) {
+          var j = i * 10;
+          fun f() { return j; }
+          fs[i] = f;
+          if (i == 1) continue;
+          if (i == 3) break;
+        }
+        print fs[0](); print fs[1](); print fs[2](); print fs[3](); print fs[4];
+
+        var gs = [nil, nil];
+        var k = 0;
+        outer: while (true) {
+          while (true) {
+            var captured = k;
+            fun g() { return captured; }
+            gs[k] = g;
+            k = k + 1;
+            if (k == 1) continue outer;
+            break outer;
+          }
+        }
+        print gs[0](); print gs[1]();
+    "#;
+    assert_eq!(output(source), "0\n10\n20\n30\nnull\n0\n1\n");
+}
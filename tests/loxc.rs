@@ -0,0 +1,134 @@
+//! 字节码缓存：源码旁边的 `.loxc` 文件。
+use std::{cell::RefCell, fs, path::PathBuf, rc::Rc};
+
+use jlox::{Backend, Compiler, Interpreter, Lox, Parser, Scanner, Vm, interpreter::Limits, loxc};
+
+// 每个测试用自己的临时目录，互不干扰
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jlox-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run_cached(path: &str, source: &str) -> (String, String) {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let diagnostics = Rc::new(RefCell::new(Vec::new()));
+    let mut lox = Lox::new_with_sinks(output.clone(), diagnostics.clone());
+    lox.set_backend(Backend::Vm);
+    let _ = lox.run_cached(path, source);
+    (
+        String::from_utf8(output.take()).unwrap(),
+        String::from_utf8(diagnostics.take()).unwrap(),
+    )
+}
+
+#[test]
+fn reuses_cache() {
+    let dir = temp_dir("reuses-cache");
+    let path = dir.join("script.lox");
+    let source = "print 1 + 2;\n";
+    fs::write(&path, source).unwrap();
+    let path = path.to_str().unwrap();
+    assert_eq!(run_cached(path, source), ("3\n".to_string(), String::new()));
+    assert!(dir.join("script.loxc").exists());
+    assert_eq!(run_cached(path, source), ("3\n".to_string(), String::new()));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn warns_about_damaged_cache() {
+    let dir = temp_dir("damaged-cache");
+    let path = dir.join("script.lox");
+    let source = "print 1 + 2;\n";
+    fs::write(&path, source).unwrap();
+    let cache = dir.join("script.loxc");
+    let path = path.to_str().unwrap();
+    run_cached(path, source);
+    let mut bytes = fs::read(&cache).unwrap();
+    bytes.pop();
+    fs::write(&cache, bytes).unwrap();
+    let warning = format!(
+        "Warning: Ignoring cache file '{}': Corrupt file: checksum mismatch.\n",
+        cache.display()
+    );
+    assert_eq!(run_cached(path, source), ("3\n".to_string(), warning));
+    // 重新编译后覆盖了损坏的缓存
+    assert_eq!(run_cached(path, source), ("3\n".to_string(), String::new()));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn source_named_loxc_is_not_overwritten() {
+    let dir = temp_dir("source-named-loxc");
+    let path = dir.join("weird.loxc");
+    let source = "print \"still here\";\n";
+    fs::write(&path, source).unwrap();
+    let path = path.to_str().unwrap();
+    for _ in 0..2 {
+        assert_eq!(run_cached(path, source).0, "still here\n");
+        assert_eq!(fs::read_to_string(path).unwrap(), source);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+// 和 loxc 里的校验和一样的 64 位 FNV-1a
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+#[test]
+fn corrupt_bytecode_with_valid_checksum() {
+    // 校验和正确、内容被改过的文件要么被拒绝，要么执行时报错，虚拟机不能崩溃
+    let source = r#"
+        class A { init(x) { this.x = x; } get() { return this.x; } }
+        class B < A { get() { return super.get() + 1; } }
+        fun counter() { var n = 0; fun add() { n = n + 1; return n; } return add; }
+        var c = counter();
+        var l = [1, 2, 3];
+        var m = {"a": 1, "b": l};
+        for (var i = 0; i < 3; i = i + 1) {
+          var j = i;
+          fun f() { return j; }
+          if (i == 1) continue;
+          l[i] = f() + c();
+        }
+        while (true) { if ("a" in m) break; }
+        print "${B(l[0]).get()} ${m["b"].length} ${-l[2]}";
+    "#;
+    let (tokens, _) = Scanner::new().scan_all(source.to_string());
+    let statements = Parser::new(tokens).parse().unwrap();
+    let script = Compiler::new().compile(&statements).unwrap();
+    let hash = loxc::source_hash(source);
+    let bytes = loxc::encode(&script, hash);
+    let header = 4 + 2 + 8 + 8;
+    let limits = Limits {
+        max_steps: Some(10_000),
+        max_memory: Some(1 << 20),
+        ..Limits::default()
+    };
+    let (mut rejected, mut failed) = (0, 0);
+    for at in header..bytes.len() {
+        for flip in [0x01, 0x02, 0x10, 0x80, 0xff] {
+            let mut bytes = bytes.clone();
+            bytes[at] ^= flip;
+            let checksum = fnv1a(&bytes[header..]);
+            bytes[header - 8..header].copy_from_slice(&checksum.to_le_bytes());
+            let Ok(script) = loxc::decode(&bytes, hash) else {
+                rejected += 1;
+                continue;
+            };
+            let mut interpreter = Interpreter::new_with_output(Rc::new(RefCell::new(Vec::new())));
+            interpreter.set_limits(limits);
+            if Vm::new().run(&mut interpreter, script).is_err() {
+                failed += 1;
+            }
+        }
+    }
+    assert!(rejected > 0 && failed > 0, "{} {}", rejected, failed);
+}
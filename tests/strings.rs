@@ -0,0 +1,72 @@
+//! 字符串的转义序列和 `${}` 插值。
+mod common;
+
+use common::{error, output};
+use jlox::interpreter::Limits;
+
+#[test]
+fn escapes() {
+    let source = r#"print "a\tb\\c\"d\$e\u{48}\u{1F600}\n";"#;
+    assert_eq!(output(source), "a\tb\\c\"d$eH\u{1F600}\n\n");
+}
+
+#[test]
+fn bad_escapes() {
+    // 报告在反斜杠的位置，一次报告所有错误
+    let diagnostics = error(
+        Limits::default(),
+        "var a = 1;\nprint \"ab\\qc\" + \"\\u{110000}\";\n",
+    );
+    assert!(
+        diagnostics.contains("Invalid escape sequence '\\q'.\n  --> test.lox:2:10"),
+        "{}",
+        diagnostics
+    );
+    assert!(
+        diagnostics.contains("Invalid unicode escape '\\u{110000}'.\n  --> test.lox:2:18"),
+        "{}",
+        diagnostics
+    );
+}
+
+#[test]
+fn interpolation() {
+    let source = r#"
+        var name = "world";
+        var m = {"k": [1, 2]};
+        print "hello ${name}!";
+        print "${1 + 2}${"a" + "b"}";
+        print "a${"b${"c${1}"}d"}e";
+        print "${m["k"][1]} ${ {"x": nil}["x"] }";
+        print "\${name}";
+    "#;
+    assert_eq!(
+        output(source),
+        "hello world!\n3ab\nabc1de\n2 null\n${name}\n"
+    );
+}
+
+#[test]
+fn bad_interpolation() {
+    for (source, expected) in [
+        (
+            "print \"${ 1 + }\";\n",
+            "Syntax error at '}': Expect expression.\n  --> test.lox:1:15",
+        ),
+        (
+            "print \"${}\";\n",
+            "Syntax error at '}': Expect expression.\n  --> test.lox:1:10",
+        ),
+        (
+            "print \"${1 2}\";\n",
+            "Syntax error at '2': Expect '}' after interpolation expression.\n  --> test.lox:1:11",
+        ),
+        (
+            "print \"${1\";\n",
+            "Lexical error: Unterminated string interpolation.\n  --> test.lox:1:8",
+        ),
+    ] {
+        let diagnostics = error(Limits::default(), source);
+        assert!(diagnostics.contains(expected), "{}", diagnostics);
+    }
+}
@@ -0,0 +1,69 @@
+//! 运行时错误的调用栈：两种执行方式打印的帧和位置都一样。
+mod common;
+
+use common::error;
+use jlox::interpreter::Limits;
+
+#[test]
+fn tracebacks() {
+    let source = r#"
+        class Box {
+          init(value) { this.value = value; }
+          twice() { return this.add(this.value); }
+          add(other) { return this.value + other; }
+        }
+        fun make() {
+          var box = Box(nil);
+          fun run() { return box.twice(); }
+          return run;
+        }
+        make()();
+    "#;
+    let diagnostics = error(Limits::default(), source);
+    for frame in ["at <script>", "at run", "at twice", "at add"] {
+        assert!(diagnostics.contains(frame), "{}", diagnostics);
+    }
+
+    // 原生函数和类的参数个数不对，错误出在调用处
+    let diagnostics = error(Limits::default(), "fun f() { return clock(1); }\nf();\n");
+    assert!(
+        diagnostics.contains("Expected 0 arguments but got 1.\n  --> test.lox:1:25"),
+        "{}",
+        diagnostics
+    );
+    assert!(
+        diagnostics.ends_with("  at <script> (test.lox:2:3)\n  at f (test.lox:1:25)\n"),
+        "{}",
+        diagnostics
+    );
+    let diagnostics = error(
+        Limits::default(),
+        "class P { init(a) {} }\nfun f() { P(); }\nf();\n",
+    );
+    assert!(
+        diagnostics.contains("Expected 1 arguments but got 0.\n  --> test.lox:2:13"),
+        "{}",
+        diagnostics
+    );
+    assert!(
+        diagnostics.ends_with("  at <script> (test.lox:3:3)\n  at f (test.lox:2:13)\n"),
+        "{}",
+        diagnostics
+    );
+}
+
+#[test]
+fn repeated_frames() {
+    // 无限递归的几百个相同的帧合成一行
+    let source = "fun f(n) {\n  return f(n + 1);\n}\nf(0);\n";
+    let diagnostics = error(Limits::default(), source);
+    let expected = "\
+Stack trace (most recent call last):
+  at <script> (test.lox:4:4)
+  at f (test.lox:2:17)
+  at f (test.lox:2:17)
+  at f (test.lox:2:17)
+  [previous line repeated 496 more times]
+";
+    assert!(diagnostics.ends_with(expected), "{}", diagnostics);
+}